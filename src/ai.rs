@@ -0,0 +1,618 @@
+use tcod::colors::*;
+use tcod::map::Map as FovMap;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+use std::mem;
+
+use crate::game::{is_blocked, message, move_by, move_towards, mut_two, Message, PLAYER};
+use crate::map::{get_new_object_id, line_of_sight, out_of_bounds, Map};
+use crate::object::{Equipment, Object};
+use crate::pathfinding::PathCache;
+use crate::scheduler::{Actor, ACTION_COST, ATTACK_COST, MOVE_COST};
+
+// How far the sound of a fight carries, walls or not
+pub const COMBAT_NOISE: i32 = 7;
+// How far a monster sees when its kind doesn't say
+const DEFAULT_SIGHT: i32 = 8;
+// Every swarming monster of the same kind this close joins in when one of them spots the player
+const SWARM_RADIUS: i32 = 8;
+// How far a wanderer strays for each new destination
+const WANDER_RANGE: i32 = 10;
+// Patrol posts are picked this close to where the monster first stood, counting that spot as one
+const PATROL_RANGE: i32 = 8;
+const PATROL_POSTS: usize = 3;
+// Random tiles tried when picking a destination before giving up for the turn
+const DESTINATION_TRIES: u32 = 10;
+
+/// What a monster does while it hasn't noticed the player.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Idle {
+    #[default]
+    Wait,
+    Wander,
+    Patrol,
+}
+
+/// An attack a monster makes from a distance instead of closing in.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ranged {
+    pub range: i32,
+    // It backs away whenever the player gets closer than this
+    #[serde(default)]
+    pub keep_distance: i32,
+}
+
+/// How a kind of monster behaves, as described in the game data.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Behavior {
+    #[serde(default)]
+    pub idle: Idle,
+    // How many tiles away it can see the player, given a clear line
+    #[serde(default = "default_sight")]
+    pub sight: i32,
+    // Whether it spawns asleep, blind to everything until something wakes it
+    #[serde(default)]
+    pub asleep: bool,
+    // Percent of its max hp at or below which it runs from the player; 0 means it never does
+    #[serde(default)]
+    pub flee_below: i32,
+    #[serde(default)]
+    pub ranged: Option<Ranged>,
+    // Spotting the player rouses every other monster of its kind nearby
+    #[serde(default)]
+    pub swarm: bool,
+}
+
+fn default_sight() -> i32 {
+    DEFAULT_SIGHT
+}
+
+/// What a monster is doing right now. Each turn its behavior picks the next state from
+/// this one and what it can see, and the state decides how it moves.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum AiState {
+    // Woken only by noise or the player stepping up next to it
+    Asleep,
+    Idle,
+    Wandering { goal: Option<(i32, i32)> },
+    Patrolling { next: usize },
+    // Chasing the player, or the last place it saw or heard them
+    Hunting { target: (i32, i32) },
+    Fleeing,
+    Confused { previous: Box<AiState>, num_turns: i32 },
+}
+
+impl AiState {
+    /// The state a monster of this behavior is in while it hasn't noticed the player.
+    pub fn calm(behavior: &Behavior) -> Self {
+        match behavior.idle {
+            Idle::Wait => AiState::Idle,
+            Idle::Wander => AiState::Wandering { goal: None },
+            Idle::Patrol => AiState::Patrolling { next: 0 },
+        }
+    }
+
+    fn is_calm(&self) -> bool {
+        matches!(self, AiState::Asleep | AiState::Idle | AiState::Wandering { .. } | AiState::Patrolling { .. })
+    }
+}
+
+/// A monster's mind: how its kind behaves, and where it has got to.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Ai {
+    pub behavior: Behavior,
+    pub state: AiState,
+    // The beat a patroller walks, picked on its first turn
+    #[serde(default)]
+    pub posts: Vec<(i32, i32)>,
+}
+
+impl Ai {
+    pub fn new(behavior: Behavior) -> Self {
+        let state = if behavior.asleep { AiState::Asleep } else { AiState::calm(&behavior) };
+        Ai { behavior, state, posts: Vec::new() }
+    }
+
+    /// A word for what the monster is up to, for describing it to the player.
+    pub fn status(&self) -> &'static str {
+        match self.state {
+            AiState::Asleep => "asleep",
+            AiState::Idle | AiState::Wandering { .. } | AiState::Patrolling { .. } => "unaware",
+            AiState::Hunting { .. } => "alert",
+            AiState::Fleeing => "fleeing",
+            AiState::Confused { .. } => "confused",
+        }
+    }
+
+    /// Leaves the monster stumbling around at random for `num_turns` turns,
+    /// after which it picks up whatever it was doing before.
+    pub fn confuse(&mut self, num_turns: i32) {
+        let previous = match mem::replace(&mut self.state, AiState::Idle) {
+            // Already confused: just restart the clock
+            AiState::Confused { previous, .. } => previous,
+            state => Box::new(state),
+        };
+        self.state = AiState::Confused { previous, num_turns };
+    }
+}
+
+/// Something loud enough for monsters to hear, such as a fight. It is heard on the next
+/// monster turn by everything within `radius` of `pos`, then forgotten.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Noise {
+    pub pos: (i32, i32),
+    pub radius: i32,
+}
+
+/// Everything a monster may look at or change on its turn.
+pub struct AiContext<'a> {
+    pub map: &'a Map,
+    // A Vec rather than a slice, so replicating monsters can add their copies
+    pub objects: &'a mut Vec<Object>,
+    pub noises: &'a mut Vec<Noise>,
+    // The player's view, only to decide what they get told about
+    pub fov_map: &'a FovMap,
+    pub messages: &'a mut Vec<Message>,
+    pub rng: &'a mut rand_pcg::Pcg32,
+    pub paths: &'a mut PathCache,
+    pub player_apps: &'a [Equipment],
+}
+
+/// Lets the monster at `monster_id` act once: first it decides what to do about what it can see,
+/// then it does it, and then it copies itself if it is due to. Returns the energy the action cost.
+pub fn take_turn(monster_id: usize, ctx: &mut AiContext) -> i32 {
+    let mut cost = ACTION_COST;
+    if let Some(mut ai) = ctx.objects[monster_id].ai.take() {
+        let state = match mem::replace(&mut ai.state, AiState::Idle) {
+            // Too busy stumbling around to notice anything
+            confused @ AiState::Confused { .. } => confused,
+            state => next_state(monster_id, &ai.behavior, state, ctx),
+        };
+        let (state, action_cost) = act(monster_id, &mut ai, state, ctx);
+        ai.state = state;
+        cost = action_cost;
+        if ctx.objects[monster_id].alive {
+            ctx.objects[monster_id].ai = Some(ai);
+        }
+    }
+    if ctx.objects[monster_id].alive && ctx.objects[monster_id].replication.is_some() {
+        replicate(monster_id, ctx);
+    }
+    cost
+}
+
+/// Counts down to the monster's next copy, and once it is due, puts a fresh one on a free tile
+/// next to it. A monster that is hemmed in, or whose kind has hit its population cap, keeps
+/// trying every turn. The copy is pushed onto the end of `objects`, so it only gets its own turn
+/// once the current one is over.
+fn replicate(monster_id: usize, ctx: &mut AiContext) {
+    let replication = match ctx.objects[monster_id].replication.as_mut() {
+        Some(replication) => replication,
+        None => return,
+    };
+    if replication.countdown > 0 {
+        replication.countdown -= 1;
+        return;
+    }
+    let max_population = replication.max_population as usize;
+
+    let monster = &ctx.objects[monster_id];
+    let population = ctx.objects.iter().filter(|object| object.alive && object.name == monster.name).count();
+    if population >= max_population {
+        return;
+    }
+    let (x, y) = monster.pos();
+    let mut free_tiles = Vec::new();
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            if !out_of_bounds(x + dx, y + dy) && !is_blocked(x + dx, y + dy, ctx.map, ctx.objects) {
+                free_tiles.push((x + dx, y + dy));
+            }
+        }
+    }
+    if free_tiles.is_empty() {
+        return;
+    }
+    let (copy_x, copy_y) = free_tiles[ctx.rng.gen_range(0, free_tiles.len())];
+
+    let mut copy = monster.clone();
+    copy.set_pos(copy_x, copy_y);
+    copy.id = get_new_object_id(ctx.objects);
+    if let Some(fighter) = copy.fighter.as_mut() {
+        fighter.hp = fighter.max_hp;
+    }
+    copy.ai = monster.ai.as_ref().map(|ai| Ai::new(ai.behavior));
+    if let Some(replication) = copy.replication.as_mut() {
+        replication.countdown = replication.every;
+    }
+    copy.actor = monster.actor.map(|actor| Actor::new(actor.speed));
+    if let Some(replication) = ctx.objects[monster_id].replication.as_mut() {
+        replication.countdown = replication.every;
+    }
+
+    if ctx.fov_map.is_in_fov(x, y) || ctx.fov_map.is_in_fov(copy_x, copy_y) {
+        message(ctx.messages, format!("{} copies itself!", copy.name), LIGHT_GREEN);
+    }
+    ctx.objects.push(copy);
+}
+
+fn next_state(monster_id: usize, behavior: &Behavior, state: AiState, ctx: &mut AiContext) -> AiState {
+    let noise = loudest_noise(monster_id, ctx);
+    let state = match state {
+        AiState::Asleep if noise.is_none() && !is_adjacent(monster_id, ctx) => return AiState::Asleep,
+        AiState::Asleep => {
+            let monster = &ctx.objects[monster_id];
+            if ctx.fov_map.is_in_fov(monster.x, monster.y) {
+                message(ctx.messages, format!("{} wakes up!", monster.name), YELLOW);
+            }
+            AiState::calm(behavior)
+        }
+        state => state,
+    };
+
+    if !sees_player(monster_id, behavior, ctx) {
+        return match state {
+            // Out of sight is safe enough
+            AiState::Fleeing => AiState::calm(behavior),
+            // Goes to find out what the noise was
+            state => noise.map_or(state, |target| AiState::Hunting { target }),
+        };
+    }
+
+    let monster = &ctx.objects[monster_id];
+    if is_wounded(monster, behavior) {
+        if matches!(state, AiState::Hunting { .. }) && ctx.fov_map.is_in_fov(monster.x, monster.y) {
+            message(ctx.messages, format!("{} breaks off and flees!", monster.name), YELLOW);
+        }
+        return AiState::Fleeing;
+    }
+
+    let player_pos = ctx.objects[PLAYER].pos();
+    if behavior.swarm && state.is_calm() {
+        rouse_swarm(monster_id, player_pos, ctx);
+    }
+    AiState::Hunting { target: player_pos }
+}
+
+/// Carries out the state's action, returning the state to be in next and what the action cost.
+fn act(monster_id: usize, ai: &mut Ai, state: AiState, ctx: &mut AiContext) -> (AiState, i32) {
+    match state {
+        AiState::Asleep => (AiState::Asleep, ACTION_COST),
+        AiState::Idle => (AiState::Idle, ACTION_COST),
+        AiState::Wandering { goal } => (wander(monster_id, goal, ctx), MOVE_COST),
+        AiState::Patrolling { next } => (patrol(monster_id, &mut ai.posts, next, ctx), MOVE_COST),
+        AiState::Hunting { target } => hunt(monster_id, &ai.behavior, target, ctx),
+        AiState::Fleeing => {
+            if step_away(monster_id, ctx) {
+                (AiState::Fleeing, MOVE_COST)
+            } else if is_adjacent(monster_id, ctx) {
+                // Cornered, so it fights
+                melee(monster_id, ctx);
+                (AiState::Fleeing, ATTACK_COST)
+            } else {
+                (AiState::Fleeing, ACTION_COST)
+            }
+        }
+        AiState::Confused { previous, num_turns } => {
+            if num_turns > 0 {
+                let direction = (ctx.rng.gen_range(-1, 2), ctx.rng.gen_range(-1, 2));
+                move_by(monster_id, direction, ctx.map, ctx.objects);
+                (AiState::Confused { previous, num_turns: num_turns - 1 }, MOVE_COST)
+            } else {
+                message(ctx.messages, format!("{} finds its route again.", ctx.objects[monster_id].name), YELLOW);
+                (*previous, ACTION_COST)
+            }
+        }
+    }
+}
+
+fn wander(monster_id: usize, goal: Option<(i32, i32)>, ctx: &mut AiContext) -> AiState {
+    let pos = ctx.objects[monster_id].pos();
+    let goal = match goal {
+        Some(goal) if !arrived(pos, goal, ctx) => Some(goal),
+        _ => pick_destination(pos, WANDER_RANGE, ctx),
+    };
+    match goal {
+        Some(goal) if step_towards(monster_id, goal, ctx) => AiState::Wandering { goal: Some(goal) },
+        _ => AiState::Wandering { goal: None },
+    }
+}
+
+fn patrol(monster_id: usize, posts: &mut Vec<(i32, i32)>, next: usize, ctx: &mut AiContext) -> AiState {
+    let pos = ctx.objects[monster_id].pos();
+    if posts.is_empty() {
+        posts.push(pos);
+        while posts.len() < PATROL_POSTS {
+            match pick_destination(pos, PATROL_RANGE, ctx) {
+                Some(post) => posts.push(post),
+                None => break,
+            }
+        }
+    }
+
+    let mut next = next % posts.len();
+    if arrived(pos, posts[next], ctx) {
+        next = (next + 1) % posts.len();
+    }
+    step_towards(monster_id, posts[next], ctx);
+    AiState::Patrolling { next }
+}
+
+fn hunt(monster_id: usize, behavior: &Behavior, target: (i32, i32), ctx: &mut AiContext) -> (AiState, i32) {
+    if sees_player(monster_id, behavior, ctx) {
+        let distance = ctx.objects[monster_id].distance_to(&ctx.objects[PLAYER]);
+        if let Some(ranged) = behavior.ranged {
+            if distance < ranged.keep_distance as f32 && step_away(monster_id, ctx) {
+                return (AiState::Hunting { target }, MOVE_COST);
+            }
+            if distance <= ranged.range as f32 {
+                let (monster, player) = mut_two(monster_id, PLAYER, ctx.objects);
+                message(ctx.messages, format!("{} fires at {}.", monster.name, player.name), ORANGE);
+                monster.attack(player, &[], ctx.player_apps, ctx.messages);
+                ctx.noises.push(Noise { pos: player.pos(), radius: COMBAT_NOISE });
+                return (AiState::Hunting { target }, ATTACK_COST);
+            }
+        }
+        if distance < 2.0 {
+            melee(monster_id, ctx);
+            return (AiState::Hunting { target }, ATTACK_COST);
+        }
+        if !step_towards(monster_id, target, ctx) {
+            // No way through, so at least close the distance
+            move_towards(monster_id, target, ctx.map, ctx.objects);
+        }
+        return (AiState::Hunting { target }, MOVE_COST);
+    }
+
+    // Lost sight of the player: search where they were last seen or heard, then give up
+    let pos = ctx.objects[monster_id].pos();
+    if arrived(pos, target, ctx) || !step_towards(monster_id, target, ctx) {
+        return (AiState::calm(behavior), ACTION_COST);
+    }
+    (AiState::Hunting { target }, MOVE_COST)
+}
+
+/// Sends every calm monster of the same kind and swarm behavior nearby after the player.
+fn rouse_swarm(monster_id: usize, target: (i32, i32), ctx: &mut AiContext) {
+    let (x, y) = ctx.objects[monster_id].pos();
+    let name = ctx.objects[monster_id].name.clone();
+    let mut roused = false;
+    // The monster calling them has its AI taken for the turn, so it isn't counted
+    for other in ctx.objects.iter_mut().filter(|other| other.alive && other.name == name) {
+        let in_earshot = (other.x - x).abs() <= SWARM_RADIUS && (other.y - y).abs() <= SWARM_RADIUS;
+        if let Some(ai) = other.ai.as_mut() {
+            if in_earshot && ai.behavior.swarm && ai.state.is_calm() {
+                ai.state = AiState::Hunting { target };
+                roused = true;
+            }
+        }
+    }
+    if roused && ctx.fov_map.is_in_fov(x, y) {
+        message(ctx.messages, format!("{} calls the rest of its swarm!", name), ORANGE);
+    }
+}
+
+fn melee(monster_id: usize, ctx: &mut AiContext) {
+    if ctx.objects[PLAYER].fighter.is_some_and(|f| f.hp > 0) {
+        let (monster, player) = mut_two(monster_id, PLAYER, ctx.objects);
+        monster.attack(player, &[], ctx.player_apps, ctx.messages);
+        ctx.noises.push(Noise { pos: player.pos(), radius: COMBAT_NOISE });
+    }
+}
+
+/// Takes the next step of the route to `goal`, returning false if there is no route.
+fn step_towards(monster_id: usize, goal: (i32, i32), ctx: &mut AiContext) -> bool {
+    let (x, y) = ctx.objects[monster_id].pos();
+    match ctx.paths.next_step(monster_id, goal, ctx.map, ctx.objects) {
+        Some((step_x, step_y)) => {
+            move_by(monster_id, (step_x - x, step_y - y), ctx.map, ctx.objects);
+            true
+        }
+        None => false,
+    }
+}
+
+/// Steps to whichever free neighbouring tile is furthest from the player, if any is further than here.
+fn step_away(monster_id: usize, ctx: &mut AiContext) -> bool {
+    let (x, y) = ctx.objects[monster_id].pos();
+    let (player_x, player_y) = ctx.objects[PLAYER].pos();
+    let distance_from_player = |(x, y): (i32, i32)| (x - player_x).pow(2) + (y - player_y).pow(2);
+
+    let mut best = None;
+    let mut best_distance = distance_from_player((x, y));
+    for dx in -1..=1 {
+        for dy in -1..=1 {
+            let step = (x + dx, y + dy);
+            if out_of_bounds(step.0, step.1) || is_blocked(step.0, step.1, ctx.map, ctx.objects) {
+                continue;
+            }
+            if distance_from_player(step) > best_distance {
+                best = Some((dx, dy));
+                best_distance = distance_from_player(step);
+            }
+        }
+    }
+    match best {
+        Some(direction) => {
+            move_by(monster_id, direction, ctx.map, ctx.objects);
+            true
+        }
+        None => false,
+    }
+}
+
+/// A random free floor tile within `range` of `pos`, if one turns up within a few tries.
+fn pick_destination((x, y): (i32, i32), range: i32, ctx: &mut AiContext) -> Option<(i32, i32)> {
+    for _ in 0..DESTINATION_TRIES {
+        let tile = (x + ctx.rng.gen_range(-range, range + 1), y + ctx.rng.gen_range(-range, range + 1));
+        if tile != (x, y) && !out_of_bounds(tile.0, tile.1) && !is_blocked(tile.0, tile.1, ctx.map, ctx.objects) {
+            return Some(tile);
+        }
+    }
+    None
+}
+
+/// Whether a monster at `pos` has got as far towards `goal` as it can: onto it,
+/// or next to it while something else stands there.
+fn arrived(pos: (i32, i32), goal: (i32, i32), ctx: &AiContext) -> bool {
+    let next_to = (pos.0 - goal.0).abs() <= 1 && (pos.1 - goal.1).abs() <= 1;
+    pos == goal || (next_to && is_blocked(goal.0, goal.1, ctx.map, ctx.objects))
+}
+
+/// Whether the player is alive, within the monster's sight and not hidden behind a wall.
+fn sees_player(monster_id: usize, behavior: &Behavior, ctx: &AiContext) -> bool {
+    let monster = &ctx.objects[monster_id];
+    let player = &ctx.objects[PLAYER];
+    player.alive
+        && monster.distance_to(player) <= behavior.sight as f32
+        && line_of_sight(ctx.map, monster.pos(), player.pos())
+}
+
+/// Where the loudest noise within earshot came from, if there was one.
+fn loudest_noise(monster_id: usize, ctx: &AiContext) -> Option<(i32, i32)> {
+    let (x, y) = ctx.objects[monster_id].pos();
+    ctx.noises
+        .iter()
+        .filter(|noise| (noise.pos.0 - x).pow(2) + (noise.pos.1 - y).pow(2) <= noise.radius.pow(2))
+        .max_by_key(|noise| noise.radius)
+        .map(|noise| noise.pos)
+}
+
+fn is_adjacent(monster_id: usize, ctx: &AiContext) -> bool {
+    ctx.objects[monster_id].distance_to(&ctx.objects[PLAYER]) < 2.0
+}
+
+fn is_wounded(monster: &Object, behavior: &Behavior) -> bool {
+    behavior.flee_below > 0 && monster.fighter.is_some_and(|f| f.hp * 100 <= f.max_hp * behavior.flee_below)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::game::tests::test_game;
+    use crate::game::GameState;
+    use crate::map::Tile;
+
+    /// Puts a monster of the built-in kind `name` at `pos` in the test room, returning its index.
+    fn add_monster(game: &mut GameState, name: &str, pos: (i32, i32)) -> usize {
+        let data = game.data.clone();
+        let id = game.objects.len() as i32;
+        game.objects.push(Object::new_monster(pos.0, pos.1, name, &data.spawns.monsters[name], id));
+        game.objects.len() - 1
+    }
+
+    fn context(game: &mut GameState) -> AiContext<'_> {
+        AiContext {
+            map: &game.map,
+            objects: &mut game.objects,
+            noises: &mut game.noises,
+            fov_map: &game.fov_map,
+            messages: &mut game.messages,
+            rng: &mut game.rng,
+            paths: &mut game.paths,
+            player_apps: &[],
+        }
+    }
+
+    /// Runs `next_state` for the monster at `monster_id` from `state`.
+    fn think(game: &mut GameState, monster_id: usize, state: AiState) -> AiState {
+        let behavior = game.objects[monster_id].ai.as_ref().unwrap().behavior;
+        next_state(monster_id, &behavior, state, &mut context(game))
+    }
+
+    fn state(game: &GameState, monster_id: usize) -> &AiState {
+        &game.objects[monster_id].ai.as_ref().unwrap().state
+    }
+
+    #[test]
+    fn hears_the_loudest_noise_in_earshot() {
+        let mut game = test_game();
+        let virus = add_monster(&mut game, "virus", (20, 15));
+        assert_eq!(loudest_noise(virus, &context(&mut game)), None);
+
+        game.noises.push(Noise { pos: (24, 15), radius: 4 });
+        game.noises.push(Noise { pos: (20, 12), radius: 3 });
+        // Too far away to be heard, however loud
+        game.noises.push(Noise { pos: (10, 15), radius: 9 });
+        assert_eq!(loudest_noise(virus, &context(&mut game)), Some((24, 15)));
+    }
+
+    #[test]
+    fn sees_the_player_only_in_range_and_in_the_open() {
+        let mut game = test_game();
+        let virus = add_monster(&mut game, "virus", (17, 15));
+        let behavior = game.objects[virus].ai.as_ref().unwrap().behavior;
+        assert!(sees_player(virus, &behavior, &context(&mut game)));
+
+        let short_sighted = Behavior { sight: 4, ..behavior };
+        assert!(!sees_player(virus, &short_sighted, &context(&mut game)));
+
+        game.map[15][15] = Tile::wall();
+        assert!(!sees_player(virus, &behavior, &context(&mut game)));
+    }
+
+    #[test]
+    fn a_sleeper_sleeps_through_silence() {
+        let mut game = test_game();
+        let spambot = add_monster(&mut game, "spambot", (20, 15));
+        assert_eq!(*state(&game, spambot), AiState::Asleep);
+        assert_eq!(think(&mut game, spambot, AiState::Asleep), AiState::Asleep);
+    }
+
+    #[test]
+    fn a_sleeper_wakes_on_noise() {
+        let mut game = test_game();
+        let spambot = add_monster(&mut game, "spambot", (25, 15));
+        game.objects[PLAYER].set_pos(12, 11);
+        game.noises.push(Noise { pos: (22, 17), radius: COMBAT_NOISE });
+        let messages = game.messages.len();
+
+        // Too far to see the player, so it goes to see what the noise was
+        assert_eq!(think(&mut game, spambot, AiState::Asleep), AiState::Hunting { target: (22, 17) });
+        // Out in the dark, so the player isn't told
+        assert_eq!(game.messages.len(), messages);
+    }
+
+    #[test]
+    fn a_sleeper_wakes_when_the_player_is_next_to_it() {
+        let mut game = test_game();
+        let spambot = add_monster(&mut game, "spambot", (13, 16));
+        assert_eq!(think(&mut game, spambot, AiState::Asleep), AiState::Hunting { target: (12, 15) });
+        assert_eq!(game.messages.last().unwrap().0, "spambot wakes up!");
+    }
+
+    #[test]
+    fn an_alert_monster_searches_where_it_lost_the_player() {
+        let mut game = test_game();
+        let virus = add_monster(&mut game, "virus", (17, 15));
+        assert_eq!(think(&mut game, virus, AiState::Patrolling { next: 0 }), AiState::Hunting { target: (12, 15) });
+
+        game.objects[PLAYER].set_pos(28, 19);
+        let searching = AiState::Hunting { target: (12, 15) };
+        assert_eq!(think(&mut game, virus, searching.clone()), searching);
+
+        game.objects[virus].ai.as_mut().unwrap().state = searching;
+        for _ in 0..10 {
+            take_turn(virus, &mut context(&mut game));
+            if state(&game, virus).is_calm() {
+                break;
+            }
+        }
+        // Having found nobody there, it goes back to its rounds
+        assert_eq!(game.objects[virus].pos(), (12, 15));
+        assert_eq!(*state(&game, virus), AiState::Patrolling { next: 0 });
+    }
+
+    #[test]
+    fn a_wounded_monster_flees_until_out_of_sight() {
+        let mut game = test_game();
+        let virus = add_monster(&mut game, "virus", (14, 15));
+        game.objects[virus].fighter.as_mut().unwrap().hp = 4;
+        assert_eq!(think(&mut game, virus, AiState::Hunting { target: (12, 15) }), AiState::Fleeing);
+        assert_eq!(game.messages.last().unwrap().0, "virus breaks off and flees!");
+
+        game.objects[PLAYER].set_pos(28, 19);
+        assert_eq!(think(&mut game, virus, AiState::Fleeing), AiState::Patrolling { next: 0 });
+    }
+}
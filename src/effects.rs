@@ -0,0 +1,193 @@
+use tcod::colors::*;
+use tcod::map::Map as FovMap;
+use std::collections::HashMap;
+
+use crate::ai::{Noise, COMBAT_NOISE};
+use crate::game::{message, Message, PLAYER};
+use crate::object::{Equipment, Item, Object};
+
+pub enum UseResult {
+    Used,
+    Cancelled,
+}
+
+/// Everything an effect may look at or change while it runs.
+pub struct EffectContext<'a> {
+    // The tile the player aimed at, for items that need a target
+    pub target: Option<(i32, i32)>,
+    pub fov_map: &'a FovMap,
+    pub objects: &'a mut [Object],
+    // Anything loud the effect does, for the monsters to hear
+    pub noises: &'a mut Vec<Noise>,
+    pub player_apps: &'a [Equipment],
+    pub messages: &'a mut Vec<Message>,
+}
+
+pub type EffectFn = fn(&Item, &mut EffectContext) -> UseResult;
+
+/// Maps the effect names used in the game data to the code that runs them.
+pub struct EffectRegistry {
+    effects: HashMap<String, EffectFn>,
+}
+
+impl EffectRegistry {
+    pub fn new() -> Self {
+        EffectRegistry { effects: HashMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, effect: EffectFn) {
+        self.effects.insert(name.to_string(), effect);
+    }
+
+    pub fn get(&self, name: &str) -> Option<EffectFn> {
+        self.effects.get(name).cloned()
+    }
+}
+
+impl Default for EffectRegistry {
+    /// A registry holding every effect the game ships with.
+    fn default() -> Self {
+        let mut registry = EffectRegistry::new();
+        registry.register("heal", cast_heal);
+        registry.register("bolt", cast_bolt);
+        registry.register("confuse", cast_confuse);
+        registry.register("area_damage", cast_area_damage);
+        registry.register("haste", cast_haste);
+        registry.register("slow", cast_slow);
+        registry
+    }
+}
+
+fn cast_heal(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    if let Some(fighter) = ctx.objects[PLAYER].fighter {
+        if fighter.hp >= ctx.objects[PLAYER].max_hp(ctx.player_apps) {
+            message(ctx.messages, "Connection strength at maximum...", GREEN);
+            return UseResult::Cancelled;
+        }
+        message(
+            ctx.messages,
+            "Connection to server strengthened.",
+            DARKER_GREEN);
+        ctx.objects[PLAYER].heal(item.param("amount"), ctx.player_apps);
+        return UseResult::Used;
+    }
+    UseResult::Cancelled
+}
+
+/// Hits the targeted monster for `damage`.
+fn cast_bolt(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    let monster_id = match target_monster(item, ctx) {
+        Some(id) => id,
+        None => return UseResult::Cancelled,
+    };
+
+    let damage = item.param("damage");
+    message(
+        ctx.messages,
+        format!("A flood of packets hits {} for {} damage.", ctx.objects[monster_id].name, damage),
+        LIGHT_BLUE);
+    ctx.objects[monster_id].take_damage(damage, ctx.messages);
+    ctx.noises.push(Noise { pos: ctx.objects[monster_id].pos(), radius: COMBAT_NOISE });
+    UseResult::Used
+}
+
+/// Leaves the targeted monster stumbling around at random for `turns` turns.
+fn cast_confuse(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    let monster_id = match target_monster(item, ctx) {
+        Some(id) => id,
+        None => return UseResult::Cancelled,
+    };
+
+    let num_turns = item.param("turns");
+    let monster = &mut ctx.objects[monster_id];
+    if let Some(ai) = monster.ai.as_mut() {
+        ai.confuse(num_turns);
+    }
+    message(
+        ctx.messages,
+        format!("{} starts routing its packets in circles!", monster.name),
+        LIGHT_GREEN);
+    UseResult::Used
+}
+
+/// Speeds the player up to `percent` of their normal speed for `turns` turns.
+fn cast_haste(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    match ctx.objects[PLAYER].actor.as_mut() {
+        Some(actor) => actor.boost(item.param("percent"), item.param("turns")),
+        None => return UseResult::Cancelled,
+    }
+    message(ctx.messages, "Your process is scheduled on every core at once.", LIGHT_CYAN);
+    UseResult::Used
+}
+
+/// Slows the targeted monster down to `percent` of its normal speed for `turns` turns.
+fn cast_slow(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    let monster_id = match target_monster(item, ctx) {
+        Some(id) => id,
+        None => return UseResult::Cancelled,
+    };
+
+    let monster = &mut ctx.objects[monster_id];
+    if let Some(actor) = monster.actor.as_mut() {
+        actor.boost(item.param("percent"), item.param("turns"));
+    }
+    message(ctx.messages, format!("{} has its bandwidth throttled!", monster.name), LIGHT_BLUE);
+    UseResult::Used
+}
+
+/// Deals `damage` to everything within `radius` of the targeted tile, the player included.
+fn cast_area_damage(item: &Item, ctx: &mut EffectContext) -> UseResult {
+    let (x, y) = match target_tile(item, ctx) {
+        Some(tile) => tile,
+        None => return UseResult::Cancelled,
+    };
+
+    let radius = item.param("radius");
+    let damage = item.param("damage");
+    message(ctx.messages, "A broadcast storm floods the network!", ORANGE);
+    ctx.noises.push(Noise { pos: (x, y), radius: COMBAT_NOISE + radius });
+    for id in 0..ctx.objects.len() {
+        let object = &ctx.objects[id];
+        let in_blast = (object.x - x).pow(2) + (object.y - y).pow(2) <= radius * radius;
+        if in_blast && object.fighter.is_some() && object.alive {
+            message(ctx.messages, format!("{} takes {} damage.", object.name, damage), ORANGE);
+            ctx.objects[id].take_damage(damage, ctx.messages);
+        }
+    }
+    UseResult::Used
+}
+
+/// The targeted tile, as long as the player can see it and it is within the item's `range`.
+fn target_tile(item: &Item, ctx: &mut EffectContext) -> Option<(i32, i32)> {
+    let (x, y) = match ctx.target {
+        Some(tile) => tile,
+        None => {
+            message(ctx.messages, "No target given.", WHITE);
+            return None;
+        }
+    };
+    if !ctx.fov_map.is_in_fov(x, y) {
+        message(ctx.messages, "Target is out of sight.", WHITE);
+        return None;
+    }
+    let range = item.param("range");
+    let (player_x, player_y) = ctx.objects[PLAYER].pos();
+    if range > 0 && (x - player_x).pow(2) + (y - player_y).pow(2) > range * range {
+        message(ctx.messages, "Target is out of range.", WHITE);
+        return None;
+    }
+    Some((x, y))
+}
+
+/// The living monster on the targeted tile.
+fn target_monster(item: &Item, ctx: &mut EffectContext) -> Option<usize> {
+    let tile = target_tile(item, ctx)?;
+    let monster_id = ctx.objects
+        .iter()
+        .enumerate()
+        .position(|(id, object)| id != PLAYER && object.pos() == tile && object.fighter.is_some() && object.alive);
+    if monster_id.is_none() {
+        message(ctx.messages, "Nothing to hit there.", WHITE);
+    }
+    monster_id
+}
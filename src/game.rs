@@ -0,0 +1,774 @@
+use tcod::colors::*;
+use tcod::map::{FovAlgorithm, Map as FovMap};
+use std::cmp;
+use std::collections::{BTreeMap, VecDeque};
+use std::mem;
+use std::rc::Rc;
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+use crate::ai::{self, AiContext, Noise, COMBAT_NOISE};
+use crate::effects::*;
+use crate::gamedata::GameData;
+use crate::map::*;
+use crate::object::*;
+use crate::pathfinding::PathCache;
+use crate::scheduler::{self, Actor, Timeline, ACTION_COST, ATTACK_COST, MOVE_COST};
+use crate::shell::{run_line, CommandRegistry};
+
+pub const PLAYER: usize = 0;
+
+const FOV_ALGO: FovAlgorithm = FovAlgorithm::Basic;
+const FOV_LIGHT_WALLS: bool = true;
+const TORCH_RADIUS: i32 = 3;
+
+// Slots in the local filesystem; stacked files share one
+pub const MAX_INVENTORY: usize = 26;
+
+// How many Apps the player can have installed at once
+pub const APP_SLOTS: usize = 3;
+
+// Stream selector for the game's Pcg32; the seed picks the starting state
+pub const RNG_STREAM: u64 = 0x2a04cd05868ddbcd;
+
+// Oldest messages are dropped once the log grows past this
+const MAX_MESSAGES: usize = 100;
+// Same again for the command prompt's history
+const MAX_HISTORY: usize = 100;
+
+pub type Message = (String, Color);
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PlayerAction {
+    // Carries the energy the action cost
+    TookTurn(i32),
+    NoTurn,
+    Exit,
+}
+
+/// A single player intent, independent of whichever frontend produced it.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Action {
+    Move((i32, i32)),
+    PickUp,
+    Command(String),
+    FollowLink,
+    RevealMap,
+}
+
+/// Running totals for the game-over summary.
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub struct RunStats {
+    pub turns: u32,
+    pub kills: u32,
+    pub files_collected: u32,
+    pub scripts_executed: u32,
+    pub deepest_server: u32,
+}
+
+/// A server the player has left, kept as it was so they can come back to it.
+#[derive(Serialize, Deserialize)]
+pub struct Server {
+    pub seed: u64,
+    pub map: Map,
+    pub objects: Vec<Object>,
+}
+
+/// Everything needed to play the game, with no dependency on a console.
+#[derive(Serialize, Deserialize)]
+pub struct GameState {
+    pub map: Map,
+    pub objects: Vec<Object>,
+    pub inventory: Vec<Object>,
+    pub messages: Vec<Message>,
+    // Derived from `map`, so it is rebuilt by `init_fov` rather than saved
+    #[serde(skip, default = "empty_fov_map")]
+    pub fov_map: FovMap,
+    pub rng: rand_pcg::Pcg32,
+    pub seed: u64,
+    pub stats: RunStats,
+    pub depth: u32,
+    pub server_seed: u64,
+    pub servers: BTreeMap<u32, Server>,
+    // Every command line the player has entered, oldest first
+    pub history: Vec<String>,
+    pub aliases: BTreeMap<String, String>,
+    // Picked when the run starts, so every server in it is carved the same way
+    pub generator: String,
+    // Commands still to run from the current command line, alias or macro
+    #[serde(skip)]
+    pub pending: VecDeque<String>,
+    // Code, not state: every load starts from the built-in effects
+    #[serde(skip)]
+    pub effects: EffectRegistry,
+    #[serde(skip)]
+    pub commands: CommandRegistry,
+    #[serde(skip)]
+    pub generators: GeneratorRegistry,
+    // Routes monsters are following, planned again as soon as they are out of date
+    #[serde(skip)]
+    pub paths: PathCache,
+    // Made since the monsters last acted, and heard on their next turn
+    #[serde(skip)]
+    pub noises: Vec<Noise>,
+    // Shared with the frontend and reattached by `load_game` rather than saved
+    #[serde(skip, default = "empty_game_data")]
+    pub data: Rc<GameData>,
+}
+
+impl GameState {
+    /// Builds a fresh game. Every random roll in the run is drawn from `seed`.
+    pub fn new(seed: u64, data: Rc<GameData>) -> Self {
+        let player = Object::new_player(0, 0, "player", '@', DARK_GREEN, 0, true, true, data.player);
+
+        let rng = rand_pcg::Pcg32::new(seed, RNG_STREAM);
+
+        let mut objects = vec![player];
+        let generators = GeneratorRegistry::default();
+        let generator = data.map_generator.clone();
+        let map = generate_server(&mut objects, seed, 1, &generators, &generator, &data.spawns);
+
+        let mut game = GameState {
+            map,
+            objects,
+            inventory: Vec::new(),
+            messages: Vec::new(),
+            fov_map: empty_fov_map(),
+            rng,
+            seed,
+            stats: RunStats { deepest_server: 1, ..RunStats::default() },
+            depth: 1,
+            server_seed: seed,
+            servers: BTreeMap::new(),
+            history: Vec::new(),
+            aliases: BTreeMap::new(),
+            generator,
+            pending: VecDeque::new(),
+            effects: EffectRegistry::default(),
+            commands: CommandRegistry::default(),
+            generators,
+            paths: PathCache::new(),
+            noises: Vec::new(),
+            data,
+        };
+
+        message(&mut game.messages,
+                "Connection Initiated. Processing...",
+                YELLOW);
+        game.init_fov();
+
+        game
+    }
+
+    /// Rebuilds the FOV map from the current tiles, e.g. after loading a save.
+    pub fn init_fov(&mut self) {
+        create_fov(&mut self.fov_map, &self.map);
+        self.compute_fov();
+    }
+
+    /// Applies the player's action and, if it consumed a turn, lets the monsters act until the player is ready again.
+    /// A command line can chain several commands, and each one that takes a turn gets its own.
+    pub fn step(&mut self, action: Action) -> PlayerAction {
+        if let Action::Command(line) = action {
+            if !self.objects[PLAYER].alive {
+                return PlayerAction::NoTurn;
+            }
+            self.remember_command(&line);
+            return run_line(self, &line);
+        }
+
+        let player_action = self.player_act(action);
+        if let PlayerAction::TookTurn(cost) = player_action {
+            self.end_turn(cost);
+        }
+        player_action
+    }
+
+    /// Lets the rest of the world act once the player has spent `cost` energy on an action.
+    pub fn end_turn(&mut self, cost: i32) {
+        self.stats.turns += 1;
+        self.compute_fov();
+        if let Some(actor) = self.objects[PLAYER].actor.as_mut() {
+            actor.spend(cost);
+        }
+        // Noises the monsters make now are left for their next turn
+        let heard = self.noises.len();
+        scheduler::run_until_ready(self, PLAYER);
+        self.noises.drain(..heard);
+        for app in self.inventory.iter_mut().filter_map(|file| file.equipment.as_mut()) {
+            app.recharge = cmp::max(app.recharge - 1, 0);
+        }
+    }
+
+    fn player_act(&mut self, action: Action) -> PlayerAction {
+        use PlayerAction::*;
+
+        let player_alive = self.objects[PLAYER].alive;
+        match (action, player_alive) {
+            (Action::RevealMap, _) => {
+                reveal_map(&mut self.map);
+                NoTurn
+            }
+            (Action::Move(direction), true) => {
+                let player_apps = installed_apps(&self.inventory);
+                let (cost, killed) = player_move_or_attack(direction, &self.map, &mut self.objects, &player_apps,
+                                                           &mut self.noises, &mut self.messages);
+                if killed {
+                    self.stats.kills += 1;
+                }
+                TookTurn(cost)
+            }
+            (Action::PickUp, true) => {
+                let item_id = self.objects
+                    .iter()
+                    .position(|object| object.pos() == self.objects[PLAYER].pos() && object.item.is_some());
+                if let Some(item_id) = item_id {
+                    if pick_up_item(item_id, &mut self.objects, &mut self.inventory, &mut self.messages) {
+                        self.stats.files_collected += 1;
+                    }
+                }
+                NoTurn
+            }
+            (Action::FollowLink, true) => {
+                self.follow_link();
+                NoTurn
+            }
+            _ => NoTurn,
+        }
+    }
+
+    fn remember_command(&mut self, line: &str) {
+        let line = line.trim();
+        if line.is_empty() || self.history.last().map(String::as_str) == Some(line) {
+            return;
+        }
+        if self.history.len() == MAX_HISTORY {
+            self.history.remove(0);
+        }
+        self.history.push(line.to_string());
+    }
+
+    fn follow_link(&mut self) {
+        let player_pos = self.objects[PLAYER].pos();
+        let link = self.objects
+            .iter()
+            .find(|object| object.pos() == player_pos && object.link.is_some())
+            .and_then(|object| object.link);
+
+        match link {
+            Some(ServerLink::Uplink) => {
+                let depth = self.depth + 1;
+                self.change_server(depth);
+                message(&mut self.messages, format!("Hopped to server {}.", depth), YELLOW);
+            }
+            Some(ServerLink::Backlink) => {
+                let depth = self.depth - 1;
+                self.change_server(depth);
+                message(&mut self.messages, format!("Returned to server {}.", depth), YELLOW);
+            }
+            None => message(&mut self.messages, "There is no link here.", WHITE),
+        }
+    }
+
+    /// Parks the current server and moves the player to the one at `depth`,
+    /// generating it the first time it is visited.
+    fn change_server(&mut self, depth: u32) {
+        let leaving = Server {
+            seed: self.server_seed,
+            map: mem::take(&mut self.map),
+            objects: self.objects.split_off(PLAYER + 1),
+        };
+        let arriving_from_above = depth > self.depth;
+        self.servers.insert(self.depth, leaving);
+        self.paths.clear();
+        self.noises.clear();
+
+        match self.servers.remove(&depth) {
+            Some(server) => {
+                self.server_seed = server.seed;
+                self.map = server.map;
+                self.objects.extend(server.objects);
+
+                // Arrive on the link that leads back to the server just left
+                let arrival = if arriving_from_above { ServerLink::Backlink } else { ServerLink::Uplink };
+                let arrival_pos = self.objects
+                    .iter()
+                    .find(|object| object.link == Some(arrival))
+                    .map(|object| object.pos());
+                if let Some((x, y)) = arrival_pos {
+                    self.objects[PLAYER].set_pos(x, y);
+                }
+            }
+            None => {
+                self.server_seed = self.rng.gen();
+                self.map = generate_server(&mut self.objects, self.server_seed, depth, &self.generators, &self.generator,
+                                           &self.data.spawns);
+            }
+        }
+
+        self.depth = depth;
+        self.stats.deepest_server = cmp::max(self.stats.deepest_server, depth);
+        self.init_fov();
+    }
+
+    /// Recomputes the player's field of view and marks everything in it as explored.
+    pub fn compute_fov(&mut self) {
+        let player = &self.objects[PLAYER];
+        self.fov_map.compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
+        for y in 0..MAP_HEIGHT {
+            for x in 0..MAP_WIDTH {
+                if self.fov_map.is_in_fov(x, y) {
+                    self.map[x as usize][y as usize].explored = true;
+                }
+            }
+        }
+    }
+}
+
+/// Every object is a slot, and the ones with an `Actor` take part in the schedule.
+impl Timeline for GameState {
+    fn slot_count(&self) -> usize {
+        self.objects.len()
+    }
+
+    fn actor(&mut self, slot: usize) -> Option<&mut Actor> {
+        self.objects[slot].actor.as_mut()
+    }
+
+    fn act(&mut self, slot: usize) -> i32 {
+        if self.objects[slot].ai.is_none() {
+            return ACTION_COST;
+        }
+        let player_apps = installed_apps(&self.inventory);
+        ai::take_turn(slot, &mut AiContext {
+            map: &self.map,
+            objects: &mut self.objects,
+            noises: &mut self.noises,
+            fov_map: &self.fov_map,
+            messages: &mut self.messages,
+            rng: &mut self.rng,
+            paths: &mut self.paths,
+            player_apps: &player_apps,
+        })
+    }
+}
+
+/// Each server's layout depends only on its own seed, so any server can be regenerated from it.
+fn generate_server(objects: &mut Vec<Object>, server_seed: u64, depth: u32, generators: &GeneratorRegistry,
+                   generator: &str, tables: &SpawnTables) -> Map {
+    // Names are checked when the game data or a save is loaded
+    let generator = generators.get(generator).unwrap_or(&MixedGenerator);
+    let mut rng = rand_pcg::Pcg32::new(server_seed, RNG_STREAM);
+    let layout = generate_layout(generator, MAP_WIDTH, MAP_HEIGHT, depth, tables, &mut rng);
+    populate(&layout, objects, &mut rng, depth, tables);
+    layout.map
+}
+
+fn empty_fov_map() -> FovMap {
+    FovMap::new(MAP_WIDTH, MAP_HEIGHT)
+}
+
+fn empty_game_data() -> Rc<GameData> {
+    Rc::new(GameData::empty())
+}
+
+fn create_fov(fov: &mut FovMap, map: &Map) {
+    for y in 0..MAP_HEIGHT {
+        for x in 0..MAP_WIDTH {
+            fov.set(
+                x,
+                y,
+                !map[x as usize][y as usize].block_sight,
+                !map[x as usize][y as usize].blocked,
+            );
+        }
+    }
+}
+
+// Movement Functions
+
+pub fn move_by(id: usize, (dx, dy): (i32, i32), map: &Map, objects: &mut [Object]) {
+    let (x, y) = objects[id].pos();
+    if !out_of_bounds(x + dx, y + dy) {
+        if !is_blocked(x + dx, y + dy, map, objects) {
+            objects[id].set_pos(x + dx, y + dy);
+        }
+    }
+
+}
+
+pub fn move_towards(id: usize, (target_x, target_y): (i32, i32), map: &Map, objects: &mut [Object]) {
+    let dx = target_x - objects[id].x;
+    let dy = target_y - objects[id].y;
+    let distance = distance(dx, dy);
+
+    let dx = (dx as f32 / distance).round() as i32;
+    let dy = (dy as f32 / distance).round() as i32;
+    move_by(id, (dx, dy), map, objects);
+}
+
+pub fn distance(dx: i32, dy: i32) -> f32 {
+    ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
+}
+
+pub fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
+    // first test the map tile
+    if map[x as usize][y as usize].blocked {
+        return true;
+    }
+    // now check for any blocking objects
+    objects
+        .iter()
+        .any(|object| object.blocks && object.pos() == (x, y))
+}
+
+// Player Functions
+
+/// Returns what the move or attack cost, and whether the attack killed its target.
+fn player_move_or_attack((dx, dy): (i32, i32), map: &Map, objects: &mut [Object], player_apps: &[Equipment],
+                         noises: &mut Vec<Noise>, messages: &mut Vec<Message>) -> (i32, bool) {
+    let x = objects[PLAYER].x + dx;
+    let y = objects[PLAYER].y + dy;
+
+    let target_id = objects
+        .iter()
+        .position(|object| object.fighter.is_some() && object.pos() == (x, y));
+
+    match target_id {
+        Some(target_id) => {
+            let (player, target) = mut_two(PLAYER, target_id, objects);
+            player.attack(target, player_apps, &[], messages);
+            noises.push(Noise { pos: (x, y), radius: COMBAT_NOISE });
+            (ATTACK_COST, !target.alive)
+        }
+        None => {
+            move_by(PLAYER, (dx, dy), map, objects);
+            (MOVE_COST, false)
+        }
+    }
+}
+
+pub fn player_death(player: &mut Object, messages: &mut Vec<Message>) {
+    message(messages,
+            format!("Connection Lost... Retrying..."),
+            GREEN);
+
+    player.character = '%';
+    player.color = DARK_GREY;
+}
+
+fn pick_up_item(object_id: usize, objects: &mut Vec<Object>, inventory: &mut Vec<Object>, messages: &mut Vec<Message>) -> bool {
+    let stack = inventory.iter().position(|file| file.stacks_with(&objects[object_id]));
+    if stack.is_none() && inventory.len() >= MAX_INVENTORY {
+        message(
+            messages,
+            format!("No space in local filesystem! Cannot pick up `{}`.", objects[object_id].name),
+            YELLOW
+        );
+        false
+    } else {
+        let item = objects.swap_remove(object_id);
+        message(
+            messages,
+            format!("Moved file: `{}` to local filesystem.", item.display_name()),
+            GREEN,
+        );
+        match stack {
+            Some(stack) => inventory[stack].count += item.count,
+            None => inventory.push(item),
+        }
+        true
+    }
+}
+
+/// Moves `count` of a stack out of the local filesystem and onto the floor under the player.
+pub fn drop_item(inventory_id: usize, count: u32, inventory: &mut Vec<Object>, objects: &mut Vec<Object>,
+                 messages: &mut Vec<Message>) {
+    let mut item = if count < inventory[inventory_id].count {
+        inventory[inventory_id].count -= count;
+        let mut split = inventory[inventory_id].clone();
+        split.count = count;
+        split
+    } else {
+        inventory.remove(inventory_id)
+    };
+
+    let (x, y) = objects[PLAYER].pos();
+    item.set_pos(x, y);
+    item.id = get_new_object_id(objects);
+    message(messages, format!("Dropped `{}`.", item.display_name()), YELLOW);
+    objects.push(item);
+}
+
+/// Runs the effect of the file at `inventory_id`, using it up unless it is an App.
+pub fn use_item(inventory_id: usize, target: Option<(i32, i32)>, game: &mut GameState) -> UseResult {
+    let inventory = &mut game.inventory;
+    let messages = &mut game.messages;
+    if let Some(item) = inventory[inventory_id].item.clone() {
+        let result = match game.effects.get(&item.effect) {
+            Some(on_use) => {
+                let player_apps = installed_apps(inventory);
+                on_use(&item, &mut EffectContext {
+                    target,
+                    fov_map: &game.fov_map,
+                    objects: &mut game.objects,
+                    noises: &mut game.noises,
+                    player_apps: &player_apps,
+                    messages,
+                })
+            }
+            None => {
+                message(
+                    messages,
+                    format!("`{}` is corrupted: unknown effect `{}`.", inventory[inventory_id].name, item.effect),
+                    YELLOW
+                );
+                return UseResult::Cancelled;
+            }
+        };
+        match result {
+            UseResult::Used => {
+                let file = &mut inventory[inventory_id];
+                match file.equipment.as_mut() {
+                    // An App's ability is used, not used up
+                    Some(app) => app.recharge = item.param("cooldown"),
+                    None if file.count > 1 => file.count -= 1,
+                    None => {
+                        inventory.remove(inventory_id);
+                    }
+                }
+            },
+            UseResult::Cancelled => {
+                message(messages, "Cancelled Item Use", WHITE);
+            },
+        }
+        result
+    } else {
+        message(
+            messages,
+            format!("The {} cannot be used.", inventory[inventory_id].name),
+            WHITE
+        );
+        UseResult::Cancelled
+    }
+}
+
+/// The equipment of every App the player has installed.
+pub fn installed_apps(inventory: &[Object]) -> Vec<Equipment> {
+    inventory
+        .iter()
+        .filter_map(|file| file.equipment)
+        .filter(|app| app.installed)
+        .collect()
+}
+
+pub fn get_inventory_item_by_name(inventory: &[Object], item: &str) -> Option<usize> {
+    let index = inventory.iter().position(|r| r.name.to_ascii_lowercase() == item.to_ascii_lowercase());
+    match index {
+        Some(i) => Some(i),
+        None => None,
+    }
+}
+
+// Monster Functions
+
+pub fn monster_death(monster: &mut Object, messages: &mut Vec<Message>) {
+    message(messages,
+            format!("{} died!", monster.name),
+            LIME);
+    monster.character = '%';
+    monster.color = DARK_GREY;
+    monster.blocks = false;
+    monster.fighter = None;
+    monster.ai = None;
+    monster.replication = None;
+    monster.actor = None;
+    monster.name = format!("remains of {}", monster.name);
+}
+
+// System Functions
+
+pub fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
+    assert!(first_index != second_index);
+    let split_at_index = cmp::max(first_index, second_index);
+    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
+    if first_index < second_index {
+        (&mut first_slice[first_index], &mut second_slice[0])
+    } else {
+        (&mut second_slice[0], &mut first_slice[second_index])
+    }
+}
+
+pub fn message<T: Into<String>>(messages: &mut Vec<Message>, message: T, color: Color) {
+    if messages.len() == MAX_MESSAGES {
+        messages.remove(0);
+    }
+
+    messages.push((message.into(), color));
+}
+
+#[cfg(test)]
+pub mod tests {
+    use super::*;
+    use std::path::Path;
+
+    use crate::gamedata::load_game_data;
+    use crate::scheduler::{EXEC_COST, NORMAL_SPEED};
+
+    fn new_game(seed: u64) -> GameState {
+        let data = load_game_data(Path::new("no-data-dir"), &EffectRegistry::default(), &GeneratorRegistry::default());
+        GameState::new(seed, Rc::new(data.unwrap()))
+    }
+
+    /// A game with the player alone in an open room, at (12, 15), holding five `junk` files.
+    pub fn test_game() -> GameState {
+        let mut game = new_game(1);
+        game.map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
+        for column in &mut game.map[10..30] {
+            for tile in &mut column[10..20] {
+                *tile = Tile::empty();
+            }
+        }
+        game.objects.truncate(PLAYER + 1);
+        game.objects[PLAYER].set_pos(12, 15);
+        game.inventory.push(script("junk", "", 5));
+        game.init_fov();
+        game
+    }
+
+    fn script(name: &str, effect: &str, count: u32) -> Object {
+        let mut file = Object::new(0, 0, name, '!', WHITE, 1, false, false);
+        file.item = Some(Item { description: String::new(), effect: effect.to_string(), targeting: Targeting::None,
+                                params: BTreeMap::new() });
+        file.item_type = Some(ItemType::Script);
+        file.count = count;
+        file
+    }
+
+    /// Adds an object that does nothing but wait its turn, so its energy shows how much time has passed.
+    fn add_clock(game: &mut GameState) -> usize {
+        let mut clock = Object::new(20, 18, "clock", 'c', WHITE, 99, false, true);
+        clock.actor = Some(Actor::new(NORMAL_SPEED));
+        game.objects.push(clock);
+        game.objects.len() - 1
+    }
+
+    fn clock_energy(game: &GameState, clock: usize) -> i32 {
+        game.objects[clock].actor.unwrap().energy
+    }
+
+    /// Walks in circles, picking up whatever is underfoot and listing files along the way.
+    fn play(game: &mut GameState) {
+        let directions = [(1, 0), (1, 1), (0, 1), (-1, 1), (-1, 0), (-1, -1), (0, -1), (1, -1)];
+        for turn in 0..200 {
+            game.step(Action::Move(directions[turn / 3 % directions.len()]));
+            game.step(Action::PickUp);
+            if turn % 25 == 0 {
+                game.step(Action::Command("ls".to_string()));
+            }
+        }
+    }
+
+    #[test]
+    fn the_same_seed_plays_out_the_same() {
+        let (mut first, mut second) = (new_game(42), new_game(42));
+        play(&mut first);
+        play(&mut second);
+        assert_eq!(serde_json::to_string(&first).unwrap(), serde_json::to_string(&second).unwrap());
+
+        let mut other = new_game(43);
+        play(&mut other);
+        assert_ne!(serde_json::to_string(&first).unwrap(), serde_json::to_string(&other).unwrap());
+    }
+
+    #[test]
+    fn a_move_costs_a_move() {
+        let mut game = test_game();
+        let clock = add_clock(&mut game);
+        assert_eq!(game.step(Action::Move((1, 0))), PlayerAction::TookTurn(MOVE_COST));
+        assert_eq!(game.objects[PLAYER].pos(), (13, 15));
+        assert_eq!(game.stats.turns, 1);
+        // The clock acted once and spent all it had
+        assert_eq!(clock_energy(&game, clock), 0);
+    }
+
+    #[test]
+    fn walls_stop_the_player() {
+        let mut game = test_game();
+        game.objects[PLAYER].set_pos(10, 15);
+        game.step(Action::Move((-1, 0)));
+        assert_eq!(game.objects[PLAYER].pos(), (10, 15));
+    }
+
+    #[test]
+    fn an_attack_costs_more_than_a_move() {
+        let mut game = test_game();
+        let clock = add_clock(&mut game);
+        let data = game.data.clone();
+        let virus = Object::new_monster(13, 15, "virus", &data.spawns.monsters["virus"], 2);
+        game.objects.push(virus);
+
+        assert_eq!(game.step(Action::Move((1, 0))), PlayerAction::TookTurn(ATTACK_COST));
+        assert_eq!(game.objects[PLAYER].pos(), (12, 15));
+        assert_eq!(clock_energy(&game, clock), ATTACK_COST - MOVE_COST);
+    }
+
+    #[test]
+    fn picking_up_nothing_takes_no_time() {
+        let mut game = test_game();
+        let clock = add_clock(&mut game);
+        assert_eq!(game.step(Action::PickUp), PlayerAction::NoTurn);
+        assert_eq!(game.stats.turns, 0);
+        assert_eq!(clock_energy(&game, clock), 0);
+    }
+
+    #[test]
+    fn picking_up_a_file_takes_no_time() {
+        let mut game = test_game();
+        let mut file = script("patch", "heal", 1);
+        file.set_pos(12, 15);
+        game.objects.push(file);
+        assert_eq!(game.step(Action::PickUp), PlayerAction::NoTurn);
+        assert_eq!(game.inventory.len(), 2);
+        assert_eq!(game.stats.files_collected, 1);
+    }
+
+    #[test]
+    fn exec_costs_more_than_a_move() {
+        let mut game = test_game();
+        let clock = add_clock(&mut game);
+        game.inventory.push(script("patch", "heal", 1));
+        game.objects[PLAYER].fighter.as_mut().unwrap().hp -= 1;
+
+        let action = game.step(Action::Command("exec patch".to_string()));
+        assert_eq!(action, PlayerAction::TookTurn(EXEC_COST));
+        assert_eq!(game.stats.scripts_executed, 1);
+        assert_eq!(clock_energy(&game, clock), EXEC_COST - MOVE_COST);
+    }
+
+    #[test]
+    fn a_misfired_exec_takes_no_time() {
+        let mut game = test_game();
+        let clock = add_clock(&mut game);
+        game.inventory.push(script("patch", "heal", 1));
+        game.inventory.push(script("corrupt", "no-such-effect", 1));
+
+        // Healing at full strength is cancelled, and so is an effect that doesn't exist
+        assert_eq!(game.step(Action::Command("exec patch".to_string())), PlayerAction::NoTurn);
+        assert_eq!(game.step(Action::Command("exec corrupt".to_string())), PlayerAction::NoTurn);
+        assert_eq!(game.stats.turns, 0);
+        assert_eq!(game.stats.scripts_executed, 0);
+        assert_eq!(clock_energy(&game, clock), 0);
+        assert_eq!(game.inventory.len(), 3);
+    }
+
+    #[test]
+    fn a_dead_player_cannot_act() {
+        let mut game = test_game();
+        game.objects[PLAYER].alive = false;
+        assert_eq!(game.step(Action::Move((1, 0))), PlayerAction::NoTurn);
+        assert_eq!(game.step(Action::Command("drop junk".to_string())), PlayerAction::NoTurn);
+        assert_eq!(game.objects[PLAYER].pos(), (12, 15));
+        assert_eq!(game.inventory[0].count, 5);
+    }
+}
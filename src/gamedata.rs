@@ -0,0 +1,453 @@
+use serde::de::DeserializeOwned;
+use serde_json::{Map as JsonMap, Value};
+use std::collections::BTreeMap;
+use std::error::Error;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::ai::Behavior;
+use crate::effects::EffectRegistry;
+use crate::map::{GeneratorRegistry, DEFAULT_GENERATOR, MAX_VAULT_SIZE};
+use crate::object::{DeathCallback, Fighter, ItemDef, ItemType, MonsterDef, SpawnTables, Spawnable, VaultDef,
+                    VaultSpawn};
+
+// Used whenever the data directory has no gamedata.json of its own
+const GAME_DATA: &str = include_str!("data/gamedata.json");
+
+pub const DATA_FILE: &str = "gamedata.json";
+const MODS_DIR: &str = "mods";
+
+/// The game data, parsed and checked once so nothing has to unwrap it mid-game.
+#[derive(Debug)]
+pub struct GameData {
+    pub player: Fighter,
+    pub spawns: SpawnTables,
+    // Which map generator new runs use, unless the command line picks another
+    pub map_generator: String,
+}
+
+/// What was wrong with the game data, and where.
+#[derive(Debug)]
+pub enum DataError {
+    Io { path: PathBuf, error: io::Error },
+    Parse { source: String, error: serde_json::Error },
+    MissingSection(&'static str),
+    NotATable(&'static str),
+    Invalid { key: String, error: serde_json::Error },
+    Rejected { key: String, reason: String },
+}
+
+impl fmt::Display for DataError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DataError::Io { path, error } => write!(f, "could not read {}: {}", path.display(), error),
+            DataError::Parse { source, error } => write!(f, "{} is not valid JSON: {}", source, error),
+            DataError::MissingSection(section) => write!(f, "game data has no `{}` section", section),
+            DataError::NotATable(section) => write!(f, "`{}` must be a table of named entries", section),
+            DataError::Invalid { key, error } => write!(f, "`{}`: {}", key, error),
+            DataError::Rejected { key, reason } => write!(f, "`{}`: {}", key, reason),
+        }
+    }
+}
+
+impl Error for DataError {}
+
+impl GameData {
+    /// A stand-in with nothing to spawn, only held until real data is attached.
+    pub fn empty() -> Self {
+        GameData {
+            player: Fighter { max_hp: 1, hp: 1, defense: 0, power: 0, on_death: DeathCallback::Player },
+            spawns: SpawnTables::default(),
+            map_generator: DEFAULT_GENERATOR.to_string(),
+        }
+    }
+
+    /// Validates game data that has already been parsed, and possibly layered with mods.
+    pub fn from_value(root: Value) -> Result<Self, DataError> {
+        let player_data = root.get("player").ok_or(DataError::MissingSection("player"))?;
+        let player: Fighter = parse_entry("player".to_string(), player_data.clone())?;
+        check_fighter("player", &player)?;
+
+        let monsters: BTreeMap<String, MonsterDef> = parse_table(&root, "monsters")?;
+        for (name, monster) in &monsters {
+            check_spawnable(&format!("monsters.{}", name), monster)?;
+            check_fighter(&format!("monsters.{}.stats", name), &monster.stats)?;
+            check_behavior(&format!("monsters.{}.ai", name), &monster.ai)?;
+            if monster.speed < 1 {
+                return Err(rejected(&format!("monsters.{}.speed", name), "must be at least 1"));
+            }
+            if let Some(replication) = monster.replicate {
+                if replication.every < 1 {
+                    return Err(rejected(&format!("monsters.{}.replicate.every", name), "must be at least 1"));
+                }
+                if replication.max_population < 1 {
+                    return Err(rejected(&format!("monsters.{}.replicate.max_population", name), "must be at least 1"));
+                }
+            }
+        }
+
+        let items: BTreeMap<String, ItemDef> = parse_table(&root, "items")?;
+        for (name, item) in &items {
+            check_spawnable(&format!("items.{}", name), item)?;
+            match (item.item_type, &item.equipment) {
+                (ItemType::Script, Some(_)) => {
+                    return Err(rejected(&format!("items.{}.equipment", name), "only Apps can be installed"));
+                }
+                (ItemType::Script, None) if item.item.effect.is_empty() => {
+                    return Err(rejected(&format!("items.{}.effect", name), "a Script must name an effect"));
+                }
+                (ItemType::App, None) => {
+                    return Err(rejected(&format!("items.{}", name), "an App needs `equipment`"));
+                }
+                _ => {}
+            }
+        }
+
+        // Vaults are optional, so older data and mods without any still load
+        let vaults: BTreeMap<String, VaultDef> = match root.get("vaults") {
+            Some(_) => parse_table(&root, "vaults")?,
+            None => BTreeMap::new(),
+        };
+        for (name, vault) in &vaults {
+            let key = format!("vaults.{}", name);
+            check_spawnable(&key, vault)?;
+            check_vault(&key, vault, &monsters, &items)?;
+        }
+        let vault_chance: u32 = match root.get("vault_chance") {
+            Some(value) => parse_entry("vault_chance".to_string(), value.clone())?,
+            None => 0,
+        };
+        if vault_chance > 100 {
+            return Err(rejected("vault_chance", "must be a percentage between 0 and 100"));
+        }
+
+        let map_generator = match root.get("map_generator") {
+            Some(value) => parse_entry("map_generator".to_string(), value.clone())?,
+            None => DEFAULT_GENERATOR.to_string(),
+        };
+
+        Ok(GameData { player, spawns: SpawnTables { monsters, items, vaults, vault_chance }, map_generator })
+    }
+
+    /// Makes sure every item's effect is one the registry can actually run.
+    pub fn check_effects(&self, effects: &EffectRegistry) -> Result<(), DataError> {
+        for (name, item) in &self.spawns.items {
+            if !item.item.effect.is_empty() && effects.get(&item.item.effect).is_none() {
+                return Err(rejected(
+                    &format!("items.{}.effect", name),
+                    &format!("unknown effect `{}`", item.item.effect),
+                ));
+            }
+        }
+        Ok(())
+    }
+
+    /// Makes sure the map generator named in the data exists.
+    pub fn check_generator(&self, generators: &GeneratorRegistry) -> Result<(), DataError> {
+        if generators.get(&self.map_generator).is_none() {
+            return Err(rejected(
+                "map_generator",
+                &format!("unknown map generator `{}` (expected one of: {})",
+                         self.map_generator, generators.names().join(", ")),
+            ));
+        }
+        Ok(())
+    }
+}
+
+/// Loads `gamedata.json` from `data_dir`, or the copy built into the binary if there is none,
+/// then layers every mod in `data_dir/mods` on top of it in alphabetical order.
+pub fn load_game_data(data_dir: &Path, effects: &EffectRegistry,
+                      generators: &GeneratorRegistry) -> Result<GameData, DataError> {
+    let base_file = data_dir.join(DATA_FILE);
+    let mut root = if base_file.is_file() {
+        eprintln!("Loading game data from {}", base_file.display());
+        read_json(&base_file)?
+    } else {
+        parse_json("built-in game data", GAME_DATA)?
+    };
+
+    for mod_file in find_mods(&data_dir.join(MODS_DIR))? {
+        eprintln!("Applying mod {}", mod_file.display());
+        merge(&mut root, read_json(&mod_file)?);
+    }
+
+    let data = GameData::from_value(root)?;
+    data.check_effects(effects)?;
+    data.check_generator(generators)?;
+    Ok(data)
+}
+
+/// Every `mods/<name>/gamedata.json`, sorted so mods always apply in the same order.
+fn find_mods(mods_dir: &Path) -> Result<Vec<PathBuf>, DataError> {
+    if !mods_dir.is_dir() {
+        return Ok(Vec::new());
+    }
+
+    let entries = fs::read_dir(mods_dir).map_err(|error| DataError::Io { path: mods_dir.to_path_buf(), error })?;
+    let mut mod_files: Vec<PathBuf> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path().join(DATA_FILE))
+        .filter(|path| path.is_file())
+        .collect();
+    mod_files.sort();
+    Ok(mod_files)
+}
+
+/// Layers `overlay` onto `base`: tables are merged key by key, a `null` deletes the key,
+/// and anything else replaces what was there.
+fn merge(base: &mut Value, overlay: Value) {
+    match (base, overlay) {
+        (Value::Object(base), Value::Object(overlay)) => {
+            for (key, value) in overlay {
+                if value.is_null() {
+                    base.remove(&key);
+                } else {
+                    merge(base.entry(key).or_insert(Value::Null), value);
+                }
+            }
+        }
+        (base, overlay) => *base = overlay,
+    }
+}
+
+fn read_json(path: &Path) -> Result<Value, DataError> {
+    let text = fs::read_to_string(path).map_err(|error| DataError::Io { path: path.to_path_buf(), error })?;
+    parse_json(&path.display().to_string(), &text)
+}
+
+fn parse_json(source: &str, text: &str) -> Result<Value, DataError> {
+    serde_json::from_str(text).map_err(|error| DataError::Parse { source: source.to_string(), error })
+}
+
+fn parse_table<T: DeserializeOwned>(root: &Value, section: &'static str) -> Result<BTreeMap<String, T>, DataError> {
+    let entries: &JsonMap<String, Value> = root
+        .get(section)
+        .ok_or(DataError::MissingSection(section))?
+        .as_object()
+        .ok_or(DataError::NotATable(section))?;
+
+    // Entry by entry, so an error can say which one is broken
+    let mut table = BTreeMap::new();
+    for (name, value) in entries {
+        let entry = parse_entry(format!("{}.{}", section, name), value.clone())?;
+        table.insert(name.clone(), entry);
+    }
+    Ok(table)
+}
+
+fn parse_entry<T: DeserializeOwned>(key: String, value: Value) -> Result<T, DataError> {
+    serde_json::from_value(value).map_err(|error| DataError::Invalid { key, error })
+}
+
+fn check_fighter(key: &str, fighter: &Fighter) -> Result<(), DataError> {
+    if fighter.max_hp <= 0 {
+        return Err(rejected(key, "max_hp must be positive"));
+    }
+    if fighter.hp <= 0 || fighter.hp > fighter.max_hp {
+        return Err(rejected(key, "hp must be between 1 and max_hp"));
+    }
+    Ok(())
+}
+
+fn check_behavior(key: &str, behavior: &Behavior) -> Result<(), DataError> {
+    if behavior.sight < 0 {
+        return Err(rejected(&format!("{}.sight", key), "cannot be negative"));
+    }
+    if behavior.flee_below < 0 || behavior.flee_below > 100 {
+        return Err(rejected(&format!("{}.flee_below", key), "must be a percentage between 0 and 100"));
+    }
+    if let Some(ranged) = behavior.ranged {
+        if ranged.range < 1 {
+            return Err(rejected(&format!("{}.ranged.range", key), "must be at least 1"));
+        }
+        if ranged.keep_distance < 0 || ranged.keep_distance > ranged.range {
+            return Err(rejected(&format!("{}.ranged.keep_distance", key), "must be between 0 and the range"));
+        }
+    }
+    Ok(())
+}
+
+fn check_spawnable<T: Spawnable>(key: &str, def: &T) -> Result<(), DataError> {
+    if def.min_depth() < 1 {
+        return Err(rejected(key, "min_depth must be at least 1"));
+    }
+    Ok(())
+}
+
+/// A vault must be a rectangle small enough to fit a room, built only from walls, floor and
+/// legend characters that name real monsters and files.
+fn check_vault(key: &str, vault: &VaultDef, monsters: &BTreeMap<String, MonsterDef>,
+               items: &BTreeMap<String, ItemDef>) -> Result<(), DataError> {
+    let template_key = format!("{}.template", key);
+    let width = vault.template.first().map_or(0, |row| row.chars().count());
+    if width == 0 {
+        return Err(rejected(&template_key, "must have at least one row"));
+    }
+    if vault.template.iter().any(|row| row.chars().count() != width) {
+        return Err(rejected(&template_key, "rows must all be the same length"));
+    }
+    if width > MAX_VAULT_SIZE || vault.template.len() > MAX_VAULT_SIZE {
+        return Err(rejected(&template_key, &format!("must fit in {0}x{0} tiles", MAX_VAULT_SIZE)));
+    }
+    for tile in vault.template.iter().flat_map(|row| row.chars()) {
+        if tile != '#' && tile != '.' && !vault.legend.contains_key(&tile) {
+            return Err(rejected(&template_key, &format!("`{}` is not a wall, floor or legend character", tile)));
+        }
+    }
+
+    for (symbol, spawn) in &vault.legend {
+        let legend_key = format!("{}.legend.{}", key, symbol);
+        match spawn {
+            _ if *symbol == '#' || *symbol == '.' => {
+                return Err(rejected(&legend_key, "walls and floor cannot be used as legend characters"));
+            }
+            VaultSpawn::Monster(name) if !monsters.contains_key(name) => {
+                return Err(rejected(&legend_key, &format!("no monster named `{}`", name)));
+            }
+            VaultSpawn::Item(name) if !items.contains_key(name) => {
+                return Err(rejected(&legend_key, &format!("no file named `{}`", name)));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+fn rejected(key: &str, reason: &str) -> DataError {
+    DataError::Rejected { key: key.to_string(), reason: reason.to_string() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+    use std::env;
+    use std::process;
+
+    fn built_in() -> Value {
+        parse_json("built-in game data", GAME_DATA).unwrap()
+    }
+
+    /// The key a validation error blames, whatever kind of error it is.
+    fn blamed_key(error: &DataError) -> String {
+        match error {
+            DataError::Invalid { key, .. } | DataError::Rejected { key, .. } => key.clone(),
+            DataError::MissingSection(section) | DataError::NotATable(section) => section.to_string(),
+            other => panic!("unexpected error: {}", other),
+        }
+    }
+
+    #[test]
+    fn built_in_data_is_valid() {
+        let data = GameData::from_value(built_in()).unwrap();
+        data.check_effects(&EffectRegistry::default()).unwrap();
+        data.check_generator(&GeneratorRegistry::default()).unwrap();
+    }
+
+    #[test]
+    fn rejects_a_missing_field() {
+        let mut root = built_in();
+        root["monsters"]["worm"].as_object_mut().unwrap().remove("glyph");
+        let error = GameData::from_value(root).unwrap_err();
+        assert!(matches!(error, DataError::Invalid { .. }));
+        assert_eq!(blamed_key(&error), "monsters.worm");
+        assert!(error.to_string().contains("glyph"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_a_missing_section() {
+        let mut root = built_in();
+        root.as_object_mut().unwrap().remove("items");
+        let error = GameData::from_value(root).unwrap_err();
+        assert!(matches!(error, DataError::MissingSection("items")));
+    }
+
+    #[test]
+    fn rejects_a_wrong_type() {
+        let mut root = built_in();
+        root["items"]["Tracert"]["spawn_weight"] = json!("lots");
+        let error = GameData::from_value(root).unwrap_err();
+        assert!(matches!(error, DataError::Invalid { .. }));
+        assert_eq!(blamed_key(&error), "items.Tracert");
+        assert!(error.to_string().contains("invalid type"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_an_out_of_range_value() {
+        let mut root = built_in();
+        root["monsters"]["worm"]["stats"]["max_hp"] = json!(0);
+        let error = GameData::from_value(root).unwrap_err();
+        assert_eq!(blamed_key(&error), "monsters.worm.stats");
+
+        let mut root = built_in();
+        root["monsters"]["daemon"]["speed"] = json!(0);
+        let error = GameData::from_value(root).unwrap_err();
+        assert_eq!(blamed_key(&error), "monsters.daemon.speed");
+    }
+
+    #[test]
+    fn rejects_an_unknown_effect() {
+        let mut root = built_in();
+        root["items"]["Tracert"]["effect"] = json!("teleport");
+        let data = GameData::from_value(root).unwrap();
+        let error = data.check_effects(&EffectRegistry::default()).unwrap_err();
+        assert_eq!(blamed_key(&error), "items.Tracert.effect");
+        assert!(error.to_string().contains("teleport"), "{}", error);
+    }
+
+    #[test]
+    fn rejects_an_unknown_generator() {
+        let mut root = built_in();
+        root["map_generator"] = json!("labyrinth");
+        let data = GameData::from_value(root).unwrap();
+        let error = data.check_generator(&GeneratorRegistry::default()).unwrap_err();
+        assert_eq!(blamed_key(&error), "map_generator");
+        assert!(error.to_string().contains("labyrinth"), "{}", error);
+    }
+
+    #[test]
+    fn merge_deletes_a_key_set_to_null() {
+        let mut base = json!({ "monsters": { "worm": { "glyph": "w" }, "virus": { "glyph": "v" } } });
+        merge(&mut base, json!({ "monsters": { "worm": null } }));
+        assert_eq!(base, json!({ "monsters": { "virus": { "glyph": "v" } } }));
+    }
+
+    #[test]
+    fn merge_overrides_nested_tables_key_by_key() {
+        let mut base = json!({ "monsters": { "worm": { "glyph": "w", "stats": { "hp": 10, "power": 3 } } } });
+        merge(&mut base, json!({ "monsters": { "worm": { "stats": { "power": 5 } } } }));
+        assert_eq!(base, json!({ "monsters": { "worm": { "glyph": "w", "stats": { "hp": 10, "power": 5 } } } }));
+    }
+
+    #[test]
+    fn merge_replaces_anything_that_is_not_a_table() {
+        let mut base = json!({ "vaults": { "core": { "template": ["#.#", "..."] } } });
+        merge(&mut base, json!({ "vaults": { "core": { "template": ["."] } } }));
+        assert_eq!(base, json!({ "vaults": { "core": { "template": ["."] } } }));
+    }
+
+    #[test]
+    fn a_mod_can_add_a_monster() {
+        let data_dir = env::temp_dir().join(format!("roguelike-mod-test-{}", process::id()));
+        let mod_dir = data_dir.join(MODS_DIR).join("trojans");
+        fs::create_dir_all(&mod_dir).unwrap();
+        let trojan = json!({ "monsters": { "trojan": {
+            "glyph": "T",
+            "color": { "r": 255, "g": 0, "b": 0 },
+            "ai": { "idle": "Wander" },
+            "spawn_weight": 5,
+            "min_depth": 2,
+            "stats": { "max_hp": 12, "hp": 12, "defense": 1, "power": 4, "on_death": "Monster" }
+        } } });
+        fs::write(mod_dir.join(DATA_FILE), trojan.to_string()).unwrap();
+
+        let loaded = load_game_data(&data_dir, &EffectRegistry::default(), &GeneratorRegistry::default());
+        fs::remove_dir_all(&data_dir).unwrap();
+        let monsters = loaded.unwrap().spawns.monsters;
+        assert_eq!(monsters["trojan"].glyph, 'T');
+        assert!(monsters.contains_key("worm"));
+    }
+}
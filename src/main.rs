@@ -1,37 +1,37 @@
 use tcod::colors::*;
 use tcod::console::*;
-use tcod::map::{FovAlgorithm, Map as FovMap};
 use tcod::input::{self, Event, Key};
-use std::cmp;
 use rand::Rng;
-use serde::{Deserialize};
-use serde_json::{Result, Value};
-use std::collections::HashMap;
-use std::slice::Iter;
-use rand_core::RngCore;
-use std::fmt;
+use std::path::PathBuf;
+use std::process;
+use std::rc::Rc;
+
+mod ai;
+mod effects;
+mod game;
+mod gamedata;
+mod map;
+mod object;
+mod pathfinding;
+mod save;
+mod scheduler;
+mod shell;
+
+use effects::*;
+use game::*;
+use gamedata::*;
+use map::*;
+use object::*;
+use save::*;
+use shell::*;
 
 const SCREEN_WIDTH: i32 = 110;
 const SCREEN_HEIGHT: i32 = 50;
 const LIMIT_FPS: i32 = 20;
-const MAP_WIDTH: i32 = 79;
-const MAP_HEIGHT: i32 = 43;
 const COLOR_DARK_WALL: Color = Color {r: 10, g: 55, b: 10};
 const COLOR_LIGHT_WALL: Color = Color {r: 30, g: 100, b: 30};
 const COLOR_DARK_GROUND: Color = Color {r: 5, g: 15, b: 5};
 const COLOR_LIGHT_GROUND: Color = Color {r: 7,g: 35,b: 7};
-const ROOM_MAX_SIZE: i32 = 10;
-const ROOM_MIN_SIZE: i32 = 6;
-const MAX_ROOMS: i32 = 30;
-const FOV_ALGO: FovAlgorithm = FovAlgorithm::Basic;
-const FOV_LIGHT_WALLS: bool = true;
-const TORCH_RADIUS: i32 = 3;
-
-const MAX_ROOM_MONSTERS: i32 = 3;
-const MAX_ROOM_ITEMS: i32 = 2;
-
-const PLAYER: usize = 0;
-const GAME_DATA: &str = include_str!("data/gamedata.json");
 
 const BAR_WIDTH: i32 = 20;
 const PANEL_HEIGHT: i32 = 7;
@@ -43,671 +43,436 @@ const V_PANEL_WIDTH: i32 = SCREEN_WIDTH - MAP_WIDTH - 1;
 const V_PANEL_X: i32 = MAP_WIDTH + 1;
 const V_PANEL_HEIGHT: i32 = SCREEN_HEIGHT;
 
-const HEAL_AMOUNT: i32 = 4;
-
-
-// Data Types
-#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, PartialOrd, Ord)]
-struct Point {
-    x: i32,
-    y: i32,
-}
+const SEED_ENV_VAR: &str = "ROGUELIKE_SEED";
+const DATA_DIR_ENV_VAR: &str = "ROGUELIKE_DATA_DIR";
+const GENERATOR_ENV_VAR: &str = "ROGUELIKE_GENERATOR";
+const DEFAULT_DATA_DIR: &str = "data";
+// Separate stream for the unexplored-tile noise so redraws never disturb the game's own rolls
+const NOISE_STREAM: u64 = 0x5851f42d4c957f2d;
 
-impl Point {
-    pub fn new(x: i32, y: i32) -> Self {
-        Point { x, y }
-    }
-}
-
-#[derive(Debug)]
-struct Object {
-    x: i32,
-    y: i32,
-    name: String,
-    character: char,
-    color: Color,
-    id: i32,
-    blocks: bool,
-    alive: bool,
-    fighter: Option<Fighter>,
-    ai: Option<Ai>,
-    item: Option<Item>,
-    item_type: Option<ItemType>,
-}
 
-impl Object {
-    pub fn new(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool) -> Self {
-        Object { x, y, name: name.into(), character: ch, color, id, blocks, alive, fighter: None, ai: None, item: None, item_type: None }
-    }
-
-    pub fn new_player(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool) -> Self {
-        let player_fighter = Fighter::new(name);
-        Object {
-            x,
-            y,
-            name: name.into(),
-            character: ch,
-            color,
-            id,
-            blocks,
-            alive,
-            fighter: Some(player_fighter),
-            ai: None,
-            item: None,
-            item_type: None,
+// Main Function
+fn main() {
+    // Bad data should stop the game here, not halfway through generating a server
+    let generators = GeneratorRegistry::default();
+    let mut data = match load_game_data(&data_dir_from_args_or_env(), &EffectRegistry::default(), &generators) {
+        Ok(data) => data,
+        Err(e) => {
+            eprintln!("Could not load game data: {}", e);
+            process::exit(1);
         }
+    };
+    if let Some(name) = generator_from_args_or_env(&generators) {
+        data.map_generator = name;
     }
+    let data = Rc::new(data);
 
-    pub fn new_monster(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool) -> Self {
-        let monster_fighter = Fighter::new(name);
-        Object {
-            x,
-            y,
-            name: name.into(),
-            character: ch,
-            color,
-            id,
-            blocks,
-            alive,
-            fighter: Some(monster_fighter),
-            ai: Some(Ai),
-            item: None,
-            item_type: None,
-        }
-    }
+    let mut root = Root::initializer()
+        .font("consolas12x12_gs_tc.png", FontLayout::Tcod)
+        .font_type(FontType::Greyscale)
+        .size(SCREEN_WIDTH, SCREEN_HEIGHT)
+        .title("Connection Lost")
+        .init();
 
-    pub fn new_item(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool, item_function: Item,
-                    item_type: ItemType) -> Self {
-        let item_func = Some(item_function);
-        let item_t = Some(item_type);
-        Object {
-            x,
-            y,
-            name: name.into(),
-            character: ch,
-            color,
-            id,
-            blocks,
-            alive,
-            fighter: None,
-            ai: None,
-            item: item_func,
-            item_type: item_t,
-        }
-    }
+    tcod::system::set_fps(LIMIT_FPS);
 
-    pub fn draw(&self, con: &mut Console) {
-        con.set_default_foreground(self.color);
-        con.put_char(self.x, self.y, self.character, BackgroundFlag::None);
-    }
+    main_menu(&data, &mut root);
+}
 
-    pub fn pos(&self) -> (i32, i32) {
-        (self.x, self.y)
-    }
+/// How a session of play ended, and what the player asked to do next.
+enum GameOutcome {
+    MainMenu,
+    NewGame(Option<u64>),
+    Quit,
+}
 
-    pub fn set_pos(&mut self, x: i32, y: i32) {
-        self.x = x;
-        self.y = y;
-    }
-    
-    pub fn distance_to(&self, other: &Object) -> f32 {
-        let dx = other.x - self.x;
-        let dy = other.y - self.y;
-        distance(dx, dy)
-    }
+fn main_menu(data: &Rc<GameData>, root: &mut Root) {
+    let mut next_seed = seed_from_args_or_env();
 
-    pub fn take_damage(&mut self, damage: i32, messages: &mut Vec<Message>) {
-        if let Some(fighter) = self.fighter.as_mut() {
-            if damage > 0 {
-                fighter.hp -= damage;
+    while !root.window_closed() {
+        render_title(root);
+        let choices = &["New connection", "Continue", "Options", "Quit"];
+        let choice = menu("", choices, 24, root);
+
+        let mut outcome = match choice {
+            Some(0) => {
+                let seed = next_seed.take().unwrap_or_else(|| rand::thread_rng().gen());
+                new_game(seed, data, root)
             }
-        }
-        
-        if let Some(fighter) = self.fighter {
-            if fighter.hp <= 0 {
-                self.alive = false;
-                fighter.on_death.callback(self, messages);
+            Some(1) => match load_game(data.clone()) {
+                Ok(game) => {
+                    println!("Resuming seed: {:#x}", game.seed);
+                    play_game(game, root)
+                }
+                Err(e) => {
+                    eprintln!("Could not load saved game: {}", e);
+                    msgbox("\nNo saved session to resume.\n", 24, root);
+                    GameOutcome::MainMenu
+                }
+            },
+            Some(2) => {
+                options_menu(&mut next_seed, root);
+                GameOutcome::MainMenu
             }
-        }
-    }
+            Some(3) => GameOutcome::Quit,
+            _ => GameOutcome::MainMenu,
+        };
 
-    pub fn attack(&mut self, target: &mut Object, messages: &mut Vec<Message>) {
-        let damage = self.fighter.map_or(0, |f| f.power) - target.fighter.map_or(0, |f| f.defense);
-        if damage > 0 {
-            message(messages,
-                    format!("{} attacks {} for {} damage", self.name, target.name, damage),
-                    WHITE);
-            target.take_damage(damage, messages);
-        } else {
-            message(messages,
-                    format!("{} attacks {}, but it has no effect!", self.name, target.name),
-                    WHITE);
+        // Restarts loop here so a new connection never has to pass back through the title
+        while let GameOutcome::NewGame(seed) = outcome {
+            let seed = seed.unwrap_or_else(|| rand::thread_rng().gen());
+            outcome = new_game(seed, data, root);
         }
-    }
 
-    pub fn heal(&mut self, amount: i32) {
-        if let Some(ref mut fighter) = self.fighter {
-            fighter.hp += amount;
-            if fighter.hp > fighter.max_hp {
-                fighter.hp = fighter.max_hp;
-            }
+        if let GameOutcome::Quit = outcome {
+            break;
         }
     }
 }
 
-enum Directions {
-    NORTH,
-    SOUTH,
-    EAST,
-    WEST,
+fn new_game(seed: u64, data: &Rc<GameData>, root: &mut Root) -> GameOutcome {
+    println!("Using seed: {:#x}", seed);
+    delete_save();
+    play_game(GameState::new(seed, data.clone()), root)
 }
 
-impl Directions {
-    pub fn iterator() -> Iter<'static, Directions> {
-        static DIRECTIONS: [Directions; 4] = [Directions::NORTH, Directions::SOUTH, Directions::EAST, Directions::WEST];
-        DIRECTIONS.into_iter()
-    }
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum PlayerAction {
-    TookTurn,
-    NoTurn,
-    Exit,
-}
-
-#[derive(Clone, Copy, Debug)]
-struct Tile {
-    blocked: bool,
-    block_sight: bool,
-    explored: bool,
-}
+fn play_game(mut game: GameState, root: &mut Root) -> GameOutcome {
+    let mut con = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
+    let mut panel = Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT);
+    let mut v_panel = Offscreen::new(V_PANEL_WIDTH, V_PANEL_HEIGHT);
+    let mut noise_rng = rand_pcg::Pcg32::new(game.seed, NOISE_STREAM);
 
-impl Tile {
-    pub fn empty() -> Self {
-        Tile{blocked: false, block_sight: false, explored: false}
-    }
+    let mut key: Key;
 
-    pub fn wall() -> Self {
-        Tile{blocked: true, block_sight: true, explored: false}
-    }
-}
+    while !root.window_closed() {
+        con.clear();
 
-type Map = Vec<Vec<Tile>>;
+        match input::check_for_event(input::KEY_PRESS) {
+            Some((_, Event::Key(k))) => key = k,
+            _ => key = Default::default(),
+        }
 
-#[derive(Clone, Copy, Debug)]
-struct Rect {
-    x1: i32,
-    y1: i32,
-    x2: i32,
-    y2: i32,
-}
+        render_all(root, &mut con, &mut panel, &mut v_panel, &game, &mut noise_rng);
+        root.flush();
 
-impl Rect {
-    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
-        Rect {
-            x1: x,
-            y1: y,
-            x2: x + w,
-            y2: y + h,
+        // A lost connection is permanent, so the save goes with it
+        if !game.objects[PLAYER].alive {
+            delete_save();
+            return game_over_screen(&game, root);
         }
-    }
 
-    pub fn center(&self) -> (i32, i32) {
-        let center_x = (self.x1 + self.x2) / 2;
-        let center_y = (self.y1 + self.y2) / 2;
-        (center_x, center_y)
+        let player_action = handle_keys(key, root, &mut game);
+        if player_action == PlayerAction::Exit {
+            break;
+        }
     }
 
-    pub fn intersects_with(&self, other: &Rect) -> bool {
-        (self.x1 <= other.x2) 
-            && (self.x2 >= other.x1)
-            && (self.y1 <= other.y2) 
-            && (self.y2 >= other.y1)
+    if let Err(e) = save_game(&game) {
+        eprintln!("Could not save game: {}", e);
     }
-}
-
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
-struct Fighter {
-    max_hp: i32,
-    hp: i32,
-    defense: i32,
-    power: i32,
-    on_death: DeathCallback
-}
+    if root.window_closed() {
+        GameOutcome::Quit
+    } else {
+        GameOutcome::MainMenu
+    }
+}
+
+fn game_over_screen(game: &GameState, root: &mut Root) -> GameOutcome {
+    let summary = format!(
+        "CONNECTION LOST\n\n\
+         Origin server: {:#x}\n\
+         Deepest server reached: {}\n\
+         Turns connected: {}\n\
+         Malware purged: {}\n\
+         Files recovered: {}\n\
+         Scripts executed: {}\n\n",
+        game.seed,
+        game.stats.deepest_server,
+        game.stats.turns,
+        game.stats.kills,
+        game.stats.files_collected,
+        game.stats.scripts_executed,
+    );
+    let choices = &["Reconnect to a new server", "Retry this server", "Main menu", "Quit"];
 
-impl Fighter {
-    pub fn new(name: &str) -> Self {
-        let fighter_data = extract_node_from_gamedata(&name).unwrap();
-        let fighter: Fighter = serde_json::from_value(fighter_data).unwrap();
-        fighter
+    loop {
+        match menu(&summary, choices, 36, root) {
+            Some(0) => return GameOutcome::NewGame(None),
+            Some(1) => return GameOutcome::NewGame(Some(game.seed)),
+            Some(2) => return GameOutcome::MainMenu,
+            Some(3) => return GameOutcome::Quit,
+            _ if root.window_closed() => return GameOutcome::Quit,
+            _ => (),
+        }
     }
 }
 
-#[derive(Clone, Copy, Debug, PartialEq, Deserialize)]
-enum DeathCallback {
-    Player,
-    Monster,
-}
-
-impl DeathCallback {
-    fn callback(self, object: &mut Object, messages: &mut Vec<Message>) {
-        use DeathCallback::*;
-        let callback: fn(&mut Object, &mut Vec<Message>) = match self {
-            Player => player_death,
-            Monster => monster_death,
+fn options_menu(next_seed: &mut Option<u64>, root: &mut Root) {
+    while !root.window_closed() {
+        render_title(root);
+        let seed_label = match next_seed {
+            Some(seed) => format!("Next server seed: {:#x}", seed),
+            None => "Next server seed: random".to_string(),
         };
-        callback(object, messages);
+        let choices = [seed_label.as_str(), "Toggle fullscreen", "Back"];
+
+        match menu("Options\n", &choices, 36, root) {
+            Some(0) => {
+                root.set_default_foreground(DARK_GREEN);
+                root.print(MSG_X, PANEL_Y, "Enter a seed, or leave blank for a random one:");
+                if let Some(text) = command_prompt(root, None) {
+                    if text.trim().is_empty() {
+                        *next_seed = None;
+                    } else if let Some(seed) = parse_seed(&text) {
+                        *next_seed = Some(seed);
+                    } else {
+                        msgbox("\nThat is not a valid seed.\n", 24, root);
+                    }
+                }
+            }
+            Some(1) => {
+                let fullscreen = root.is_fullscreen();
+                root.set_fullscreen(!fullscreen);
+            }
+            _ => break,
+        }
     }
 }
 
-fn extract_node_from_gamedata(node: &str) -> Result<Value> {
-    let game_data: Value = serde_json::from_str(&GAME_DATA)?;
-    let target_node: Value = game_data[node].clone();
-
-    Ok(target_node)
-}
-
-#[derive(Clone, Copy, Debug, PartialEq)]
-struct Ai;
-
-type Message = (String, Color);
+/// Reads a seed from `--seed <n>`, then the `ROGUELIKE_SEED` environment variable.
+/// Without either, each new connection rolls its own.
+fn seed_from_args_or_env() -> Option<u64> {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--seed") {
+        match args.get(i + 1).and_then(|value| parse_seed(value)) {
+            Some(seed) => return Some(seed),
+            None => eprintln!("Ignoring invalid --seed value"),
+        }
+    }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum Item {
-    Heal,
-}
+    if let Ok(value) = std::env::var(SEED_ENV_VAR) {
+        match parse_seed(&value) {
+            Some(seed) => return Some(seed),
+            None => eprintln!("Ignoring invalid {} value", SEED_ENV_VAR),
+        }
+    }
 
-#[derive(Clone, Copy, Debug, PartialEq)]
-enum ItemType {
-    Script,
-    //App,
+    None
 }
 
-impl fmt::Display for ItemType {
-    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        match *self {
-            ItemType::Script => write!(f, "Script"),
+fn data_dir_from_args_or_env() -> PathBuf {
+    let args: Vec<String> = std::env::args().collect();
+    if let Some(i) = args.iter().position(|arg| arg == "--data-dir") {
+        match args.get(i + 1) {
+            Some(dir) => return PathBuf::from(dir),
+            None => eprintln!("Ignoring --data-dir without a directory"),
         }
     }
-}
 
-enum UseResult {
-    Used,
-    Cancelled,
-}
+    if let Ok(dir) = std::env::var(DATA_DIR_ENV_VAR) {
+        return PathBuf::from(dir);
+    }
 
-enum CommandType<T, E> {
-    Execute(T),
-    Invalid(E),
+    PathBuf::from(DEFAULT_DATA_DIR)
 }
 
+/// Reads a map generator name from `--generator <name>`, then the `ROGUELIKE_GENERATOR`
+/// environment variable. Without either, the game data decides.
+fn generator_from_args_or_env(generators: &GeneratorRegistry) -> Option<String> {
+    let args: Vec<String> = std::env::args().collect();
+    let name = match args.iter().position(|arg| arg == "--generator") {
+        Some(i) => args.get(i + 1).cloned(),
+        None => std::env::var(GENERATOR_ENV_VAR).ok(),
+    };
 
-// Main Function
-fn main() {
-    let mut root = Root::initializer()
-        .font("consolas12x12_gs_tc.png", FontLayout::Tcod)
-        .font_type(FontType::Greyscale)
-        .size(SCREEN_WIDTH, SCREEN_HEIGHT)
-        .title("Connection Lost")
-        .init();
-    
-    let mut con = Offscreen::new(SCREEN_WIDTH, SCREEN_HEIGHT);
-    let mut panel = Offscreen::new(SCREEN_WIDTH, PANEL_HEIGHT);
-    let mut v_panel = Offscreen::new(V_PANEL_WIDTH, V_PANEL_HEIGHT);
-    
-    tcod::system::set_fps(LIMIT_FPS);
-    let player = Object::new_player(0, 0, "player", '@', DARK_GREEN, 0, true, true);
-
-    let mut objects = vec![player];
-    let mut inventory: Vec<Object> = Vec::new();
-    let mut messages: Vec<Message> = Vec::new();
-    let mut map = make_map_hauberk(&mut objects);
-    //let mut map = make_map(&mut objects);
-
-    message(&mut messages,
-            "Connection Initiated. Processing...",
-            YELLOW);
-
-    let mut fov_map = FovMap::new(MAP_WIDTH, MAP_HEIGHT);
-    create_fov(&mut fov_map, &map);
-
-    let mut previous_player_position = (-1, -1);
-
-    let mut key: Key = Default::default();
-
-    while !root.window_closed() {
-        con.clear();
-
-        match input::check_for_event(input::KEY_PRESS) {
-            Some((_, Event::Key(k))) => key = k,
-            _ => key = Default::default(),
+    match name {
+        Some(name) if generators.get(&name).is_some() => Some(name),
+        Some(name) => {
+            eprintln!("Ignoring unknown map generator `{}` (expected one of: {})", name, generators.names().join(", "));
+            None
         }
+        None => None,
+    }
+}
 
-        let fov_recompute = previous_player_position != (objects[PLAYER].pos());
-        render_all(&mut root, &mut con, &mut panel, &mut v_panel, &objects, &mut map, &mut fov_map, fov_recompute, &mut messages, &inventory);
-        root.flush();
-        previous_player_position = objects[PLAYER].pos();
-        let player_action = handle_keys(key, &mut root, &mut map, &mut objects, &mut messages, &mut inventory);
-        match player_action {
-            PlayerAction::Exit => break,
-            PlayerAction::TookTurn => {
-                for id in 0..objects.len() {
-                    if objects[id].ai.is_some() {
-                        ai_take_turn(id, &map, &mut objects, &fov_map, &mut messages)
-                    }
-                }
-            },
-            PlayerAction::NoTurn => (),
-        }
+/// Accepts either decimal or `0x`-prefixed hexadecimal seeds.
+fn parse_seed(text: &str) -> Option<u64> {
+    let text = text.trim();
+    if text.starts_with("0x") || text.starts_with("0X") {
+        u64::from_str_radix(&text[2..], 16).ok()
+    } else {
+        text.parse().ok()
     }
 }
 
-fn handle_keys(key: Key, 
-    root: &mut Root, 
-    map: &mut Map, 
-    objects: &mut Vec<Object>, 
-    messages: &mut Vec<Message>, 
-    inventory: &mut Vec<Object>) 
-    -> PlayerAction {
+fn handle_keys(key: Key, root: &mut Root, game: &mut GameState) -> PlayerAction {
 
     use tcod::input::KeyCode::*;
     use PlayerAction::*;
     use Directions::*;
 
-    let player_alive = objects[PLAYER].alive;
     //let key = root.wait_for_keypress(true);
-    match (key, player_alive) {
-       (Key {
+    match key {
+       Key {
             code: Enter,
             alt: true,
             ..
-        },
-        _,
-        ) => {
+        } => {
             // Alt+Enter: toggle fullscreen
             let fullscreen = root.is_fullscreen();
             root.set_fullscreen(!fullscreen);
             NoTurn
         }
-        (Key { code: Escape, .. }, _) => return Exit,
-        (Key { code: F1, .. }, _) => {
-            reveal_map(map);
-            NoTurn
-        }
-        (Key { code: Up, .. }, true) => {
-            player_move_or_attack(get_direction(&NORTH), map, objects, messages);
-            TookTurn
-        }
-        (Key { code: Down, .. }, true) => {
-            player_move_or_attack(get_direction(&SOUTH), map, objects, messages);
-            TookTurn
-        },
-        (Key { code: Left, .. }, true) => {
-            player_move_or_attack(get_direction(&WEST), map, objects, messages);
-            TookTurn
-        },
-        (Key { code: Right, .. }, true) => {
-            player_move_or_attack(get_direction(&EAST), map, objects, messages);
-            TookTurn
-        },
-        (Key { printable: ',', ..}, true) => {
-            let item_id = objects
-                .iter()
-                .position(|object| object.pos() == objects[PLAYER].pos() && object.item.is_some());
-            if let Some(item_id) = item_id {
-                pick_up_item(item_id, objects, inventory, messages);
+        Key { code: Escape, .. } => Exit,
+        Key { code: F1, .. } => game.step(Action::RevealMap),
+        Key { code: Up, .. } => game.step(Action::Move(get_direction(&NORTH))),
+        Key { code: Down, .. } => game.step(Action::Move(get_direction(&SOUTH))),
+        Key { code: Left, .. } => game.step(Action::Move(get_direction(&WEST))),
+        Key { code: Right, .. } => game.step(Action::Move(get_direction(&EAST))),
+        Key { printable: ',', ..} => game.step(Action::PickUp),
+        Key { printable: '>', ..} | Key { printable: '<', ..} => game.step(Action::FollowLink),
+        Key { printable: '`', ..} if game.objects[PLAYER].alive => {
+            match command_prompt(root, Some(game)) {
+                Some(cmd) => run_command_line(cmd, root, game),
+                None => NoTurn,
             }
+        }
+        Key { printable: 'i', ..} if game.objects[PLAYER].alive => inventory_menu(root, game),
+        Key { printable: 'l', ..} => {
+            // Looking around is free, so whatever the cursor ends up on is ignored
+            pick_target(root, game, Targeting::Monster, "Look");
             NoTurn
-        },
-        (Key { printable: '`', ..}, true) => {
-            let command = command_prompt(root);
-            match command {
-                Some(cmd) => { 
-                    let parsed = parse_command(cmd);
-                    match parsed {
-                        CommandType::Execute(c) => {
-                            let index = get_inventory_item_by_name(inventory, &c);
-                            match index {
-                                Some(i) => { 
-                                    use_item(i, inventory, objects, messages);
-                                    return TookTurn
-                                }
-                                None => { message(
-                                    messages,
-                                    "File Not Found",
-                                    YELLOW
-                                    );
-                                    return NoTurn
-                                }
-                            };
-                        },
-                        CommandType::Invalid(_) => {
-                            message(
-                                messages,
-                                "Invalid Command Entered",
-                                YELLOW
-                            );
-                        }
-                    }
-                 },
-                None => { 
-                    //println!("Error encountered"); 
-                    return NoTurn
-                }
-            }
-            TookTurn
         }
 
         _ => NoTurn,
     }
 }
 
-// Movement Functions
-
-fn get_direction(d: &Directions) -> (i32, i32) {
-    // chooses direction
-    match d {
-        Directions::NORTH => (0, -1),
-        Directions::SOUTH => (0, 1),
-        Directions::EAST => (1, 0),
-        Directions::WEST => (-1, 0),
-    }
-}
-
-fn out_of_bounds(x: i32, y: i32) -> bool {
-    if x > 0 && x < MAP_WIDTH && y > 0 && y < MAP_HEIGHT {
-        return false
+/// Runs a command line, first letting the player aim it on the map if it needs a target it does not have.
+fn run_command_line(cmd: String, root: &mut Root, game: &mut GameState) -> PlayerAction {
+    match missing_target(game, &cmd) {
+        Some(targeting) => match pick_target(root, game, targeting, "Target") {
+            Some((x, y)) => game.step(Action::Command(format!("{} {},{}", cmd.trim_end(), x, y))),
+            None => PlayerAction::NoTurn,
+        },
+        None => game.step(Action::Command(cmd)),
     }
-    true
 }
 
-fn move_by(id: usize, (dx, dy): (i32, i32), map: &Map, objects: &mut [Object]) {
-    let (x, y) = objects[id].pos();
-    if !out_of_bounds(x + dx, y + dy) {
-        if !is_blocked(x + dx, y + dy, map, objects) {
-            objects[id].set_pos(x + dx, y + dy);
-        }
+/// Lists the local filesystem, then offers what can be done with the chosen file. Every
+/// choice runs the same command the player could have typed at the prompt.
+fn inventory_menu(root: &mut Root, game: &mut GameState) -> PlayerAction {
+    if game.inventory.is_empty() {
+        msgbox("\nLocal filesystem is empty.\n", 30, root);
+        return PlayerAction::NoTurn;
     }
 
-}
-
-fn move_towards(id: usize, (target_x, target_y): (i32, i32), map: &Map, objects: &mut [Object]) {
-    let dx = target_x - objects[id].x;
-    let dy = target_y - objects[id].y;
-    let distance = distance(dx, dy);
-
-    let dx = (dx as f32 / distance).round() as i32;
-    let dy = (dy as f32 / distance).round() as i32;
-    move_by(id, (dx, dy), map, objects);
-}
-
-fn distance(dx: i32, dy: i32) -> f32 {
-    ((dx.pow(2) + dy.pow(2)) as f32).sqrt()
-}
-
-fn is_blocked(x: i32, y: i32, map: &Map, objects: &[Object]) -> bool {
-    // first test the map tile
-    if map[x as usize][y as usize].blocked {
-        return true;
-    }
-    // now check for any blocking objects
-    objects
+    let files: Vec<String> = game.inventory
         .iter()
-        .any(|object| object.blocks && object.pos() == (x, y))
-}
-
-// Player Functions
-
-fn player_move_or_attack((dx, dy): (i32, i32), map: &Map, objects: &mut [Object], messages: &mut Vec<Message>) {
-    let x = objects[PLAYER].x + dx;
-    let y = objects[PLAYER].y + dy;
-
-    let target_id = objects
-        .iter()
-        .position(|object| object.fighter.is_some() && object.pos() == (x, y));
-
-    match target_id {
-        Some(target_id) => {
-            let (player, target) = mut_two(PLAYER, target_id, objects);
-            player.attack(target, messages);
-        }
-        None => {
-            move_by(PLAYER, (dx, dy), map, objects);
-        }
-    }
-}
-
-fn player_death(player: &mut Object, messages: &mut Vec<Message>) {
-    message(messages,
-            format!("Connection Lost... Retrying..."),
-            GREEN);
-
-    player.character = '%';
-    player.color = DARK_GREY;
-}
-
-fn pick_up_item(object_id: usize, objects: &mut Vec<Object>, inventory: &mut Vec<Object>, messages: &mut Vec<Message>) {
-    if inventory.len() >= 26 {
-        message(
-            messages,
-            format!("No space in local filesystem! Cannot pick up `{}`.", objects[object_id].name),
-            YELLOW
-        );
+        .map(|file| match (file.item_type, file.equipment) {
+            (Some(item_type), Some(app)) if app.installed => format!("{} :: {} (installed)", file.display_name(), item_type),
+            (Some(item_type), _) => format!("{} :: {}", file.display_name(), item_type),
+            (None, _) => file.display_name(),
+        })
+        .collect();
+    let file = match menu("Local Filesystem\n", &files, 44, root) {
+        Some(id) => &game.inventory[id],
+        None => return PlayerAction::NoTurn,
+    };
+
+    let name = quote_word(&file.name);
+    let has_effect = file.item.as_ref().is_some_and(|item| !item.effect.is_empty());
+    // `None` is the inspect entry, which only shows a box and never takes a turn
+    let mut actions: Vec<(&str, Option<String>)> = Vec::new();
+    if has_effect {
+        actions.push(("Execute", Some(format!("exec {}", name))));
+    }
+    match file.equipment {
+        Some(app) if app.installed => actions.push(("Uninstall", Some(format!("uninstall {}", name)))),
+        Some(_) => actions.push(("Install", Some(format!("install {}", name)))),
+        None => {}
+    }
+    if file.count > 1 {
+        actions.push(("Drop one", Some(format!("drop {} 1", name))));
+        actions.push(("Drop all", Some(format!("drop {}", name))));
     } else {
-        let item = objects.swap_remove(object_id);
-        message(
-            messages,
-            format!("Moved file: `{}` to local filesystem.", item.name),
-            GREEN,
-        );
-        inventory.push(item);
+        actions.push(("Drop", Some(format!("drop {}", name))));
+    }
+    actions.push(("Inspect", None));
+
+    let labels: Vec<&str> = actions.iter().map(|(label, _)| *label).collect();
+    let details = describe_file(file).join("\n");
+    let description = file.item.as_ref().map_or(String::new(), |item| item.description.clone());
+    match menu(&format!("{}\n", file.display_name()), &labels, 30, root) {
+        Some(choice) => match actions[choice].1.clone() {
+            Some(cmd) => run_command_line(cmd, root, game),
+            None => {
+                msgbox(&format!("{}\n\n{}\n", details, description), 44, root);
+                PlayerAction::NoTurn
+            }
+        },
+        None => PlayerAction::NoTurn,
     }
 }
 
-fn use_item(inventory_id: usize, inventory: &mut Vec<Object>, objects: &mut [Object], messages: &mut Vec<Message>) {
-    use Item::*;
+/// Moves a cursor over the map to pick a tile, drawn straight onto the last rendered frame, with
+/// `label` and what is under it shown in the message panel. Arrow keys move it, Tab jumps between
+/// visible monsters, Enter picks and Escape cancels.
+fn pick_target(root: &mut Root, game: &GameState, targeting: Targeting, label: &str) -> Option<(i32, i32)> {
+    let player = &game.objects[PLAYER];
+    let mut monsters: Vec<&Object> = game.objects
+        .iter()
+        .filter(|object| object.ai.is_some() && object.alive && game.fov_map.is_in_fov(object.x, object.y))
+        .collect();
+    monsters.sort_by(|a, b| a.distance_to(player).partial_cmp(&b.distance_to(player)).unwrap());
 
-    if let Some(item) = inventory[inventory_id].item {
-        let on_use = match item {
-            Heal => cast_heal,
-        };
-        match on_use(inventory_id, objects, messages) {
-            UseResult::Used => {
-                inventory.remove(inventory_id);
-            },
-            UseResult::Cancelled => {
-                message(messages, "Cancelled Item Use", WHITE);
-            },
-        }
-    } else {
-        message(
-            messages,
-            format!("The {} cannot be used.", inventory[inventory_id].name),
-            WHITE
-        );
+    let mut next_monster = 0;
+    let mut cursor = player.pos();
+    if targeting == Targeting::Monster && !monsters.is_empty() {
+        cursor = monsters[0].pos();
+        next_monster = 1;
     }
-}
 
-fn cast_heal(_inventory_id: usize, objects: &mut [Object], messages: &mut Vec<Message>) -> UseResult {
-    if let Some(fighter) = objects[PLAYER].fighter {
-        if fighter.hp == fighter.max_hp {
-            message(messages, "Connection strength at maximum...", GREEN);
-            return UseResult::Cancelled;
-        }
-        message(
-            messages,
-            "Connection to server strengthened.",
-            DARKER_GREEN);
-        objects[PLAYER].heal(HEAL_AMOUNT);
-        return UseResult::Used;
-    }
-    UseResult::Cancelled
-}
+    loop {
+        let (x, y) = cursor;
+        let description = format!("{} {}", label, describe_tile(game, cursor));
 
-fn parse_command(command: String) -> CommandType<String, &'static str> {
-    let parts = command.split(" ");
-    let collected = parts.collect::<Vec<&str>>();
+        let background = root.get_char_background(x, y);
+        root.set_char_background(x, y, LIGHT_GREEN, BackgroundFlag::Set);
+        root.set_default_foreground(WHITE);
+        root.print(MSG_X, PANEL_Y + 1, format!("{:<1$}", description, MSG_WIDTH as usize));
+        root.flush();
 
-    if collected.len() < 1 {
-        return CommandType::Invalid("Invalid Command");
-    } else {
-        match collected[0] {
-            "exec" => {
-                let return_value = collected[1];
-                CommandType::Execute(return_value.to_string())
+        let key = root.wait_for_keypress(true);
+        root.set_char_background(x, y, background, BackgroundFlag::Set);
+        if root.window_closed() {
+            return None;
+        }
+
+        use tcod::input::KeyCode::*;
+        let (dx, dy) = match key.code {
+            Enter => return Some(cursor),
+            Escape => return None,
+            Up => (0, -1),
+            Down => (0, 1),
+            Left => (-1, 0),
+            Right => (1, 0),
+            Tab if !monsters.is_empty() => {
+                cursor = monsters[next_monster % monsters.len()].pos();
+                next_monster += 1;
+                (0, 0)
             }
-            _ => CommandType::Invalid("Invalid Command"),
-        }
-    }
-}
-
-fn get_inventory_item_by_name(inventory: &mut Vec<Object>, item: &str) -> Option<usize> {
-    let index = inventory.iter().position(|r| r.name.to_ascii_lowercase() == item.to_ascii_lowercase());
-    match index {
-        Some(i) => Some(i),
-        None => None,
-    }
-}
-
-// AI Functions
-
-fn ai_take_turn(monster_id: usize, map: &Map, objects: &mut [Object], fov_map: &FovMap, messages: &mut Vec<Message>) {
-    let (monster_x, monster_y) = objects[monster_id].pos();
-    if fov_map.is_in_fov(monster_x, monster_y) {
-        if objects[monster_id].distance_to(&objects[PLAYER]) >= 2.0 {
-            let (player_x, player_y) = objects[PLAYER].pos();
-            move_towards(monster_id, (player_x, player_y), map, objects);
-        } else if objects[PLAYER].fighter.map_or(false, |f| f.hp > 0) {
-            let (monster, player) = mut_two(monster_id, PLAYER, objects);
-            monster.attack(player, messages);
+            _ => (0, 0),
+        };
+        if !out_of_bounds(x + dx, y + dy) {
+            cursor = (cursor.0 + dx, cursor.1 + dy);
         }
     }
 }
 
-fn monster_death(monster: &mut Object, messages: &mut Vec<Message>) {
-    message(messages,
-            format!("{} died!", monster.name),
-            LIME);
-    monster.character = '%';
-    monster.color = DARK_GREY;
-    monster.blocks = false;
-    monster.fighter = None;
-    monster.ai = None;
-    monster.name = format!("remains of {}", monster.name);
-}
-
-// System Functions
-
-fn mut_two<T>(first_index: usize, second_index: usize, items: &mut [T]) -> (&mut T, &mut T) {
-    assert!(first_index != second_index);
-    let split_at_index = cmp::max(first_index, second_index);
-    let (first_slice, second_slice) = items.split_at_mut(split_at_index);
-    if first_index < second_index {
-        (&mut first_slice[first_index], &mut second_slice[0])
-    } else {
-        (&mut second_slice[0], &mut first_slice[second_index])
-    }
+fn draw_object(object: &Object, con: &mut Offscreen) {
+    con.set_default_foreground(object.color);
+    con.put_char(object.x, object.y, object.character, BackgroundFlag::None);
 }
 
 // Rendering
@@ -716,17 +481,15 @@ fn render_all(root: &mut Root,
     con: &mut Offscreen, 
     panel: &mut Offscreen,
     v_panel: &mut Offscreen,
-    objects: &[Object], 
-    map: &mut Map,
-    fov_map: &mut FovMap,
-    fov_recompute: bool,
-    messages: &Vec<Message>,
-    inventory: &Vec<Object>,
+    game: &GameState,
+    noise_rng: &mut rand_pcg::Pcg32,
 ) {
-    if fov_recompute {
-        let player = &objects[PLAYER];
-        fov_map.compute_fov(player.x, player.y, TORCH_RADIUS, FOV_LIGHT_WALLS, FOV_ALGO);
-    } 
+    let objects = &game.objects;
+    let map = &game.map;
+    let fov_map = &game.fov_map;
+    let messages = &game.messages;
+    let inventory = &game.inventory;
+
     let characters = vec!['!', '#', '$', '&', '*', '+', '/', '0', '1', '2', '3', '4', '5', '6', '7', '8', '9',
                           '[', ']', '{', '}', 'A', 'B', 'C', 'D', 'E', 'F', 'G', 'H', 'I', 'J', 'K', 'L', 'M',
                           'N', 'O', 'P', 'Q', 'R', 'S', 'T', 'U', 'V', 'W', 'X', 'Y', 'Z'];
@@ -734,23 +497,20 @@ fn render_all(root: &mut Root,
         for x in 0..MAP_WIDTH {
             let visible = fov_map.is_in_fov(x, y);
             let wall = map[x as usize][y as usize].block_sight;
-            let explored = &mut map[x as usize][y as usize].explored;
-            if visible {
-                *explored = true;
-            }
+            let explored = map[x as usize][y as usize].explored;
             let color = match (visible, wall) {
                 (false, true) => COLOR_DARK_WALL,
                 (false, false) => COLOR_DARK_GROUND,
                 (true, true) => COLOR_LIGHT_WALL,
                 (true, false) => COLOR_LIGHT_GROUND,
             };
-            if *explored {
+            if explored {
                 con.set_char_background(x, y, color, BackgroundFlag::Set);
             }
             else {
-                let random_chance = rand::thread_rng().gen_range(0, 100);
+                let random_chance = noise_rng.gen_range(0, 100);
                 if random_chance < 10 {
-                    let random_index = rand::thread_rng().gen_range(0, characters.len());
+                    let random_index = noise_rng.gen_range(0, characters.len());
                     let chosen_char = &characters[random_index];
                     con.set_default_foreground(COLOR_DARK_WALL);
                     con.put_char(x, y, *chosen_char, BackgroundFlag::Set);
@@ -762,19 +522,21 @@ fn render_all(root: &mut Root,
     // Rendering Objects
     let mut to_draw: Vec<_> = objects
         .iter()
-        .filter(|o| fov_map.is_in_fov(o.x, o.y))
+        .filter(|o| fov_map.is_in_fov(o.x, o.y)
+                || (o.link.is_some() && map[o.x as usize][o.y as usize].explored))
         .collect();
     to_draw.sort_by(|o1, o2| {o1.blocks.cmp(&o2.blocks) });
     for object in &to_draw {
-        object.draw(con);
+        draw_object(object, con);
     }
 
     // Bottom Panel
     panel.set_default_background(BLACK);
     panel.clear();
 
+    let player_apps = installed_apps(inventory);
     let hp = objects[PLAYER].fighter.map_or(0, |f| f.hp);
-    let max_hp = objects[PLAYER].fighter.map_or(0, |f| f.max_hp);
+    let max_hp = objects[PLAYER].max_hp(&player_apps);
     panel.set_default_foreground(DARKER_GREEN);
     panel.print(1, 1, "Connection Strength");
     render_bar(
@@ -811,16 +573,32 @@ fn render_all(root: &mut Root,
 
     v_panel.set_default_foreground(DARK_GREEN);
     v_panel.print_ex(2, 2, BackgroundFlag::None, TextAlignment::Left, "Current Server");
-    v_panel.print_ex(2, 3, BackgroundFlag::None, TextAlignment::Left, format!("{}", 0x844cfa4bf95ef68 as u64));
+    v_panel.print_ex(2, 3, BackgroundFlag::None, TextAlignment::Left, format!("{:#x}", game.server_seed));
+    v_panel.print_ex(2, 4, BackgroundFlag::None, TextAlignment::Left, format!("Depth: {}", game.depth));
 
     v_panel.print_ex(2, 7, BackgroundFlag::None, TextAlignment::Left, "Available Files");
     let mut inv_y = 8 as i32;
     v_panel.set_default_foreground(GREEN);
     for item in inventory.iter() {
-        v_panel.print_ex(2, inv_y, BackgroundFlag::None, TextAlignment::Left, format!("{} :: {}", &item.name, item.item_type.unwrap()));
+        v_panel.print_ex(2, inv_y, BackgroundFlag::None, TextAlignment::Left, format!("{} :: {}", item.display_name(), item.item_type.unwrap()));
         inv_y += 1;
     }
 
+    v_panel.set_default_foreground(DARK_GREEN);
+    v_panel.print_ex(2, 36, BackgroundFlag::None, TextAlignment::Left,
+                     format!("Installed Apps ({}/{})", player_apps.len(), APP_SLOTS));
+    v_panel.set_default_foreground(GREEN);
+    let installed = inventory.iter().filter(|file| file.equipment.is_some_and(|app| app.installed));
+    for (app_y, file) in (37..).zip(installed) {
+        v_panel.print_ex(2, app_y, BackgroundFlag::None, TextAlignment::Left, &file.name);
+    }
+    v_panel.set_default_foreground(DARK_GREEN);
+    v_panel.print_ex(2, 41, BackgroundFlag::None, TextAlignment::Left,
+                     format!("Power: {}", objects[PLAYER].power(&player_apps)));
+    v_panel.print_ex(2, 42, BackgroundFlag::None, TextAlignment::Left,
+                     format!("Defense: {}", objects[PLAYER].defense(&player_apps)));
+    v_panel.set_default_foreground(GREEN);
+
     let (player_x, player_y) = objects[PLAYER].pos();
     v_panel.print_ex(V_PANEL_WIDTH / 2, SCREEN_HEIGHT - 3, BackgroundFlag::None, TextAlignment::Center, "Current Position");
     v_panel.print_ex(V_PANEL_WIDTH / 2, SCREEN_HEIGHT - 2, BackgroundFlag::None, TextAlignment::Center, format!("({}, {})", player_x, player_y));
@@ -836,18 +614,6 @@ fn render_all(root: &mut Root,
     blit(v_panel, (0, 0), (V_PANEL_WIDTH, V_PANEL_HEIGHT), root, (V_PANEL_X, 0), 1.0, 1.0);
 }
 
-fn create_fov(fov: &mut FovMap, map: &Map) {
-    for y in 0..MAP_HEIGHT {
-        for x in 0..MAP_WIDTH {
-            fov.set(
-                x,
-                y,
-                !map[x as usize][y as usize].block_sight,
-                !map[x as usize][y as usize].blocked,
-            );
-        }
-    }
-}
 
 fn render_bar(
     panel: &mut Offscreen,
@@ -903,19 +669,15 @@ fn render_border(panel: &mut Offscreen, border_color: Color, width: i32, height:
     }
 }
 
-fn message<T: Into<String>>(messages: &mut Vec<Message>, message: T, color: Color) {
-    if messages.len() == MSG_HEIGHT {
-        messages.remove(0);
-    }
-
-    messages.push((message.into(), color));
-}
-
 fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root) -> Option<usize> {
     assert!(options.len() <= 26,
     "Cannot have a menu with more than 26 options");
 
-    let header_height = root.get_height_rect(0, 0, width, SCREEN_HEIGHT, header);
+    let header_height = if header.is_empty() {
+        0
+    } else {
+        root.get_height_rect(0, 0, width, SCREEN_HEIGHT, header)
+    };
     let height = options.len() as i32 + header_height;
 
     let mut window = Offscreen::new(width, height);
@@ -961,560 +723,155 @@ fn menu<T: AsRef<str>>(header: &str, options: &[T], width: i32, root: &mut Root)
     }
 }
 
-fn command_prompt(root: &mut Root) -> Option<String> {
-    let mut prompt = Offscreen::new(MSG_WIDTH, 1);
-    let cmd_prompt = vec![':', '>', ' ', '_'];
-    let mut command = String::new();
-
-    let mut x = 0;
-    prompt.set_default_foreground(GREEN);
-    for ch in cmd_prompt.iter() {
-        prompt.put_char(x, 0, *ch, BackgroundFlag::None);
-        x += 1;
-    }
-    blit(&mut prompt, (0, 0), (MSG_WIDTH, 1), root, (MSG_X, PANEL_Y + 1), 1.0, 1.0);
-
-    root.flush();
-    let mut key = root.wait_for_keypress(true);
-
-    let mut cursor_x: usize = 3;
-    let cursor: char = '_';
-
-    while key.code != tcod::input::KeyCode::Enter {
-        if key.code == tcod::input::KeyCode::Escape {
-            return None
-        }
-
-        if key.printable.is_alphanumeric() || key.printable == ' ' {
-            let ch = key.printable.to_ascii_lowercase();
-            cursor_x += 1;
-            prompt.put_char(cursor_x as i32 - 1, 0, ch, BackgroundFlag::None);
-            prompt.put_char(cursor_x as i32, 0, cursor, BackgroundFlag::None);
-
-            command.push(ch);
-        } 
-        
-        if key.code == tcod::input::KeyCode::Backspace && cursor_x > 3 {
-            prompt.put_char(cursor_x as i32, 0, ' ', BackgroundFlag::None);
-            cursor_x -= 1;
-            prompt.put_char(cursor_x as i32, 0, cursor, BackgroundFlag::None);
-            command.pop();
-        }
-
-        blit(&mut prompt, (0, 0), (MSG_WIDTH, 1), root, (MSG_X, PANEL_Y + 1), 1.0, 1.0);
-        root.flush();
-        key = root.wait_for_keypress(true);
-
-    }
-    Some(command)
-}
-
-
-// Map Functions
-
-fn make_map(objects: &mut Vec<Object>) -> Map {
-    // fill map with "blocked" tiles
-    let mut map = vec![vec![Tile::wall(); MAP_HEIGHT as usize]; MAP_WIDTH as usize];
-    // map algo
-    let mut rooms = vec![];
-    for _ in 0..MAX_ROOMS {
-        let w = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-        let h = rand::thread_rng().gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-        let x = rand::thread_rng().gen_range(0, MAP_WIDTH - w);
-        let y = rand::thread_rng().gen_range(0, MAP_HEIGHT - h);
-
-        let new_room = Rect::new(x, y, w, h);
-
-        let failed = rooms
-            .iter()
-            .any(|other_room| new_room.intersects_with(other_room));
-        
-        if !failed {
-            if rand::random::<f32>() < 0.15 {
-                create_circle_room(new_room, &mut map);
-            } else {
-                create_room(new_room, &mut map);
-            }
-            let room_center = new_room.center();
-            place_objects(new_room, objects, &mut map);
-
-            if rooms.is_empty() {
-                objects[PLAYER].set_pos(room_center.0, room_center.1);
-            } else {
-                let prev_center = rooms[rooms.len() - 1].center();
-                if rand::random() {
-                    create_h_tunnel(prev_center.0, room_center.0, prev_center.1, &mut map);
-                    create_v_tunnel(prev_center.1, room_center.1, room_center.0, &mut map);
-                } else {
-                    create_v_tunnel(prev_center.1, room_center.1, prev_center.0, &mut map);
-                    create_h_tunnel(prev_center.0, room_center.0, room_center.1, &mut map);
-                }
-            }
-
-            rooms.push(new_room);
-        }
-    }
-
-    map
-}
-
-fn create_room(room: Rect, map: &mut Map) {
-    for x in (room.x1 + 1)..room.x2 {
-        for y in (room.y1 + 1)..room.y2 {
-            map[x as usize][y as usize] = Tile::empty();
-        }
-    }
+fn msgbox(text: &str, width: i32, root: &mut Root) {
+    let options: &[&str] = &[];
+    menu(text, options, width, root);
 }
 
-fn create_circle_room(room: Rect, map: &mut Map) {
-    // Code from @Agka on roguelikedev-help Discord channel - many thanks!
-    let rdx = room.x2 - room.x1;
-    let rdy = room.y2 - room.y1;
-
-    let div_val = cmp::min(rdx, rdy);
-
-    let radius: f32 = (div_val as f32 / 2.0) - 1.0;
-    let rad_floor: i32 = radius.floor() as i32;
-    let radsqr = radius.floor().powf(2.0) as i32;
-    let (center_x, center_y) = room.center();
-
-    let x_ratio = cmp::max(rdx / rdy, 1);
-    let y_ratio = cmp::max(rdy / rdx, 1);
-
-    for x in center_x - rad_floor - 1..center_x + rad_floor + 1 {
-        for y in center_y - rad_floor - 1..center_y + rad_floor + 1 {
-            let dx = (x - center_x) / x_ratio;
-            let dy = (y - center_y) / y_ratio;
-            let distsqr = dx.pow(2) + dy.pow(2);
-            if distsqr < radsqr {
-                map[x as usize][y as usize] = Tile::empty();
-            }
-        }
-    }
-}
-
-fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
-    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
-}
-
-fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
-    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
-        map[x as usize][y as usize] = Tile::empty();
-    }
+fn render_title(root: &mut Root) {
+    root.set_default_background(BLACK);
+    root.clear();
+    root.set_default_foreground(GREEN);
+    root.print_ex(SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2 - 6, BackgroundFlag::None, TextAlignment::Center, "CONNECTION LOST");
+    root.set_default_foreground(DARK_GREEN);
+    root.print_ex(SCREEN_WIDTH / 2, SCREEN_HEIGHT / 2 - 4, BackgroundFlag::None, TextAlignment::Center, "a roguelike by GraySentinel");
 }
 
-fn place_objects(room: Rect, objects: &mut Vec<Object>, map: &mut Map) {
-    let num_monsters = rand::thread_rng().gen_range(0, MAX_ROOM_MONSTERS + 1);
-
-    for _ in 0..num_monsters {
-        let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-        let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
-
-        let monster = if rand::random::<f32>() < 0.8 {
-            Object::new_monster(x, y, "worm", 'w', DESATURATED_GREEN, get_new_object_id(&objects), true, true)
-        } else {
-            Object::new_monster(x, y, "virus", 'v', DARKER_GREEN, get_new_object_id(&objects), true, true)
-        };
-
-        objects.push(monster);
-    }
-
-    let num_items = rand::thread_rng().gen_range(0, MAX_ROOM_ITEMS + 1);
-    //println!("Num items: {}", num_items);
-
-    for _ in 0..num_items {
-        let x = rand::thread_rng().gen_range(room.x1 + 1, room.x2);
-        let y = rand::thread_rng().gen_range(room.y1 + 1, room.y2);
-
-        let object = Object::new_item(
-            x, 
-            y, 
-            "Tracert", 
-            '!', 
-            DARK_GREEN, 
-            get_new_object_id(&objects), 
-            false, 
-            false, 
-            Item::Heal,
-            ItemType::Script);
-        objects.push(object);
-        //println!("Placed item at ({}, {})", x, y);
-
-    }
-}
-
-fn reveal_map(map: &mut Map) {
-    for x in 0..MAP_WIDTH {
-        for y in 0..MAP_HEIGHT {
-            map[x as usize][y as usize].explored = true;
-        }
-    }
-}
-
-fn get_new_object_id(objects: &Vec<Object>) -> i32 {
-    let last_item = objects.last();
-    match last_item {
-        Some(n) => n.id + 1,
-        None => 1,
-    }
-}
-
-// The Hauberk Map Generater
-
-fn make_map_hauberk(objects: &mut Vec<Object>) -> Map {
-
-    let num_room_tries = 100;
-    let extra_connector_chance = 25;
-    //let room_extra_size = 0;
-    let winding_percent = 10;
-
-    let mut current_region: i32 = -1;
-
-    let mut map_width: i32 = MAP_WIDTH;
-    let mut map_height: i32 = MAP_HEIGHT;
-
-    // let seed: u64 = 0xcafef00dd15ea5e5;
-    // let state: u64 = 0xa02bdbf7bb3c0a7;
-
-    /* let mut seed_rng = rand::thread_rng();
-    let state: u64 = seed_rng.gen();
-    let stream: u64 = seed_rng.gen(); */
-
-    let state: u64 = 0x844cfa4bf95ef68;
-    let stream: u64 = 0x2a04cd05868ddbcd;
-    println!("Using seed: {:#x} & state: {:#x}", state, stream);
-    let mut r = rand_pcg::Pcg32::new(state, stream);
-
-    if map_width % 2 == 0 {
-        map_width -= 1;
-    }
-
-    if map_height % 2 == 0 {
-        map_height -= 1;
-    }
-
-    let mut map = vec![vec![Tile::wall(); map_height as usize]; MAP_WIDTH as usize];
-
-    type VecRegion = Vec<Vec<i32>>;
-
-    let mut _regions = vec![vec![0; map_height as usize]; MAP_WIDTH as usize];
-
-    //fn on_decorate_room(room: Rect) {}
+/// Reads a line of input in the message panel. With a game to draw on, Up/Down walk its command
+/// history and Tab completes command and file names.
+fn command_prompt(root: &mut Root, game: Option<&GameState>) -> Option<String> {
+    let mut prompt = Offscreen::new(MSG_WIDTH, 1);
+    let history: &[String] = game.map_or(&[], |game| &game.history);
+
+    let mut line: Vec<char> = Vec::new();
+    let mut cursor = 0;
+    let mut history_pos = history.len();
+    // Whatever was being typed before the player started walking back through history
+    let mut draft: Vec<char> = Vec::new();
+    // Candidates Tab is cycling through, and where the word they replace starts
+    let mut cycle: Option<(usize, Vec<String>, usize)> = None;
+
+    loop {
+        draw_prompt(&mut prompt, &line, cursor);
+        blit(&mut prompt, (0, 0), (MSG_WIDTH, 1), root, (MSG_X, PANEL_Y + 1), 1.0, 1.0);
+        root.flush();
 
-    fn pcg_choose<T>(v: &Vec<T>, r: &mut rand_pcg::Pcg32) -> Option<usize> {
-        if v.is_empty() {
-            return None
-        } else {
-            let vec_length: f64 = v.len() as f64;
-            let num_digits = vec_length.log10().round() as u32;
-            let modulo: u32 = (10 as u32).pow(num_digits);
-            let mut index_option = r.next_u32() % modulo;
-            while v.len()-1 < index_option as usize {
-                index_option = r.next_u32() % modulo;
+        let key = root.wait_for_keypress(true);
+        if root.window_closed() {
+            return None;
+        }
+        if key.code != tcod::input::KeyCode::Tab {
+            cycle = None;
+        }
+
+        use tcod::input::KeyCode::*;
+        match key.code {
+            Enter => return Some(line.iter().collect()),
+            Escape => return None,
+            Left => cursor = cursor.saturating_sub(1),
+            Right if cursor < line.len() => cursor += 1,
+            Home => cursor = 0,
+            End => cursor = line.len(),
+            Backspace if cursor > 0 => {
+                cursor -= 1;
+                line.remove(cursor);
             }
-            return Some(index_option as usize)
-        }
-    }
-
-    fn grow_maze(map: &mut Map, start: Point, current_region: &mut i32, winding_percent: i32, _regions: &mut VecRegion,
-                 r: &mut rand_pcg::Pcg32) {
-        let mut cells = Vec::new();
-        let mut last_dir = (0, 0);
-
-        start_region(current_region);
-        carve(&start, map, _regions, current_region);
-
-        cells.push(start);
-
-        while !cells.is_empty() {
-            let cell = cells.last().unwrap();
-
-            let mut unmade_cells = Vec::new();
-            for d in Directions::iterator() {
-                let (dx, dy) = get_direction(d);
-                let target_pos: Point = Point::new(cell.x + dx, cell.y + dy);
-                if can_carve(map, target_pos, d) {
-                    unmade_cells.push((dx, dy));
-                }
+            Delete if cursor < line.len() => {
+                line.remove(cursor);
             }
-
-            if !unmade_cells.is_empty() {
-                let mut dir = (0, 0);
-                if unmade_cells.contains(&last_dir) && (r.next_u32() % 100) > winding_percent as u32 {
-                    dir = last_dir;
-                } else {
-                    let index_option = pcg_choose(&unmade_cells, r);
-                    match index_option {
-                        Some(i) => {dir = unmade_cells[i];}
-                        None => ()
-                    }
-                    /* let dir_choice = unmade_cells.choose(&mut rand::thread_rng());
-                    match dir_choice {
-                        Some(d) => {dir = *d;}
-                        None => ()
-                    } */
+            Up if history_pos > 0 => {
+                if history_pos == history.len() {
+                    draft = line.clone();
                 }
-
-                let close_pos = Point::new(cell.x + dir.0, cell.y + dir.1);
-                let far_pos = Point::new(cell.x + (dir.0 * 2), cell.y + (dir.1 * 2));
-                carve(&close_pos, map, _regions, current_region);
-                carve(&far_pos, map, _regions, current_region);
-
-                cells.push(far_pos);
-
-                last_dir = dir;
-
-            } else {
-                cells.pop();
-                last_dir = (0, 0);
-            }
-        }
-
-    }
-
-    fn pcg_range(r: &mut rand_pcg::Pcg32, min: i32, max: i32) -> i32 {
-        let num_digits = (max as f32).log10().round() as u32;
-        let modulo: u32 = (10 as u32).pow(num_digits);
-
-        let mut result: i32 = max + 1;
-        while !(result > min && result < max) {
-            let pcg = r.next_u32() % modulo;
-            result = pcg as i32;
-        }
-        
-        result
-    }
-
-    fn add_rooms(objects: &mut Vec<Object>, map: &mut Map, tries: i32, current_region: &mut i32, _regions: &mut VecRegion,
-                 map_width: i32, map_height: i32, r: &mut rand_pcg::Pcg32) {
-        let mut rooms = Vec::new();
-        for _ in 0..=tries {
-            let w = pcg_range(r, ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-            let h = pcg_range(r, ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
-            let x = (pcg_range(r, 0, map_width - w - 1) / 2)* 2 + 1;
-            let y = (pcg_range(r, 0, map_height - h - 1) / 2) * 2 + 1;
-
-            let new_room = Rect::new(x, y, w, h);
-
-            let failed = rooms
-            .iter()
-            .any(|other_room| new_room.intersects_with(other_room));
-
-            if rooms.is_empty() {
-                let room_center = new_room.center();
-                objects[PLAYER].set_pos(room_center.0, room_center.1);
+                history_pos -= 1;
+                line = history[history_pos].chars().collect();
+                cursor = line.len();
             }
-
-            if !failed {
-                place_objects(new_room, objects, map);
-                rooms.push(new_room);
-                start_region(current_region);
-                create_room_hauberk(&new_room, map, _regions, current_region);
+            Down if history_pos < history.len() => {
+                history_pos += 1;
+                line = match history.get(history_pos) {
+                    Some(entry) => entry.chars().collect(),
+                    None => draft.clone(),
+                };
+                cursor = line.len();
             }
-        }
-    }
-
-    fn connect_regions(map: &mut Map, _regions: &mut VecRegion, current_region: i32, extra_chance: i32,
-                       map_width: i32, map_height: i32, r: &mut rand_pcg::Pcg32) {
-                           
-        let mut connector_regions = HashMap::new();
-
-        for x in 1..map_width-1 {
-            for y in 1..map_height-1 {
-                if !map[x as usize][y as usize].block_sight { continue; }
-
-                let mut regions = Vec::new();
-                for d in Directions::iterator() {
-                    let (dx, dy) = get_direction(d);
-                    let region = _regions[(x + dx) as usize][(y + dy) as usize];
-                    regions.push(region);
+            Tab => {
+                if let Some(game) = game {
+                    complete_word(game, &mut line, &mut cursor, &mut cycle);
                 }
-
-                if regions.len() < 2 { continue; }
-
-                connector_regions.insert(Point::new(x, y), regions);
             }
-        }
-
-        let mut connectors: Vec<_> = connector_regions.keys().collect();
-        connectors.sort();
-        //println!("{:?}", connectors);
-
-        let mut merged = HashMap::new();
-        let mut open_regions = Vec::new();
-
-        //println!("{}", current_region);
-
-        for i in 0..current_region {
-            merged.insert(i, i);
-            open_regions.push(i);
-        }
-
-        //println!("{:?}", open_regions);
-
-        while !open_regions.is_empty() {
-            let connector_idx = pcg_choose(&connectors, r);
-            let mut regions = Vec::new();
-            match connector_idx {
-                Some(c) => {
-                    let connector = connectors[c];
-                    add_junction(connector, map/*, _regions, current_region, r*/);
-                    if connector_regions.contains_key(connector) {
-                        for region in &connector_regions[connector] {
-                            if merged.contains_key(&region) {
-                                let actual_region = merged[&region];
-                                regions.push(actual_region);
-                            }
-                        }
-                    }
-
-                    let dest = regions.first();
-                    let mut dest_region = 0;
-                    match dest {
-                        Some(region) => { dest_region = *region; }
-                        None => ()
-                    }
-
-                    let sources: Vec<_> = regions[1..].iter().collect();
-
-                    for i in 0..current_region {
-                        if sources.contains(&&merged[&i]) {
-                            if merged.contains_key(&i) {
-                                merged.remove(&i);
-                                merged.insert(i, dest_region);
-                            }
-                        }
-                    }
-
-                    for s in sources {
-                        let index = open_regions.iter().position(|x| *x == *s);
-                        match index {
-                            Some(n) => { 
-                                if open_regions.len() >= n {
-                                    open_regions.remove(n); 
-                                }
-                            }
-                            None => ()
-                        }
-                    }
-
-                    let mut to_be_removed = Vec::new();
-                    for pos in &connectors {
-                        if distance(connector.x-pos.x, connector.y-pos.y) < 2.0 {
-                            to_be_removed.push(*pos);
-                            continue;
-                        }
-
-                        let mut local_regions = Vec::new();
-                        for r in &connector_regions[connector] {
-                            if merged.contains_key(&r) {
-                                let region_actual = merged[&r];
-                                local_regions.push(region_actual);
-                            }
-                        }
-
-                        if local_regions.len() > 1 { continue; }
-
-                        let new_junc = r.next_u32() % 100;
-                        //let new_junc: u32 = 1;
-                        if new_junc < extra_chance as u32 {
-                            add_junction(pos, map/*, _regions, current_region, r*/);
-                        }
-
-                        if local_regions.len() == 1 {
-                            to_be_removed.push(*pos);
-                        }
-                    }
-
-                    connectors.retain(|&x| !to_be_removed.contains(&x));
-                }
-                None => ()
-            }
-
-        }
-
-        
-    }
-
-    fn add_junction(pos: &Point, map: &mut Map/*, _regions: &mut VecRegion, current_region: i32, r: &mut rand_pcg::Pcg32*/) {
-        //println!("Adding junction at: ({}, {})", pos.x, pos.y);
-        map[pos.x as usize][pos.y as usize] = Tile::empty();
-    }
-
-    fn remove_dead_ends(map: &mut Map, map_width: i32, map_height: i32) {
-        let mut done = false;
-
-        while !done {
-            done = true;
-
-            for x in 1..map_width {
-                for y in 1..map_height {
-                    if map[x as usize][y as usize].block_sight { continue; }
-
-                    let mut exits = 0;
-                    for d in Directions::iterator() {
-                        let (dx, dy) = get_direction(d);
-                        let (target_x, target_y) = (x + dx, y + dy);
-                        if !map[target_x as usize][target_y as usize].block_sight { exits += 1; }
-                    }
-
-                    if exits != 1 { continue; }
-
-                    done = false;
-                    map[x as usize][y as usize] = Tile::wall();
-                }
+            _ if !key.printable.is_control() => {
+                line.insert(cursor, key.printable);
+                cursor += 1;
             }
+            _ => {}
         }
-
     }
+}
 
-    fn can_carve(map: &mut Map, pos: Point, d: &Directions) -> bool {
-        let (dx, dy) = get_direction(d);
-        let test_point = (pos.x + (dx*3), pos.y + (dy*3));
-        if out_of_bounds(test_point.0, test_point.1) {
-            return false
-        }
-
-        let (target_x, target_y) = (pos.x + dx, pos.y + dy);
+fn draw_prompt(prompt: &mut Offscreen, line: &[char], cursor: usize) {
+    let cmd_prompt = ":> ";
+    let prompt_len = cmd_prompt.len() as i32;
+    // Scroll long lines so the cursor always stays on screen
+    let visible = (MSG_WIDTH - prompt_len - 1) as usize;
+    let offset = cursor.saturating_sub(visible);
 
-        return map[target_x as usize][target_y as usize].block_sight;
+    prompt.clear();
+    prompt.set_default_foreground(GREEN);
+    prompt.print(0, 0, cmd_prompt);
+    for (x, ch) in line.iter().skip(offset).take(visible + 1).enumerate() {
+        prompt.put_char(prompt_len + x as i32, 0, *ch, BackgroundFlag::None);
     }
 
-    fn start_region(i: &mut i32) {
-        *i = *i + 1;
+    let cursor_x = prompt_len + (cursor - offset) as i32;
+    if cursor == line.len() {
+        prompt.put_char(cursor_x, 0, '_', BackgroundFlag::None);
+    } else {
+        prompt.set_char_background(cursor_x, 0, DARK_GREEN, BackgroundFlag::Set);
     }
+}
 
-    fn carve(pos: &Point, map: &mut Map, _regions: &mut VecRegion, current_region: &mut i32) {
-        map[pos.x as usize][pos.y as usize] = Tile::empty();
-        //println!("Made ({}, {}) a floor tile", pos.x, pos.y);
-        _regions[pos.x as usize][pos.y as usize] = *current_region;
+/// Completes the word before the cursor. A unique match is filled in, several are narrowed
+/// to their common prefix, and pressing Tab again cycles through them.
+fn complete_word(game: &GameState, line: &mut Vec<char>, cursor: &mut usize,
+                 cycle: &mut Option<(usize, Vec<String>, usize)>) {
+    if let Some((start, candidates, i)) = cycle.as_mut() {
+        *i = (*i + 1) % candidates.len();
+        replace_word(line, cursor, *start, &candidates[*i]);
+        return;
     }
 
-    fn create_room_hauberk(room: &Rect, map: &mut Map, _regions: &mut VecRegion, current_region: &mut i32) {
-        for x in room.x1..room.x2 {
-            for y in room.y1..room.y2 {
-                let target_point = Point::new(x, y);
-                carve(&target_point, map, _regions, current_region);
+    let before: String = line[..*cursor].iter().collect();
+    let (start_byte, candidates) = completions(game, &before);
+    let start = before[..start_byte].chars().count();
+    match candidates.len() {
+        0 => {}
+        1 => {
+            replace_word(line, cursor, start, &format!("{} ", candidates[0]));
+        }
+        _ => {
+            let prefix = common_prefix(&candidates);
+            if prefix.chars().count() > *cursor - start {
+                replace_word(line, cursor, start, &prefix);
+            } else {
+                replace_word(line, cursor, start, &candidates[0]);
+                *cycle = Some((start, candidates, 0));
             }
         }
     }
+}
 
-    add_rooms(objects, &mut map, num_room_tries, &mut current_region, &mut _regions, map_width, map_height, &mut r);
-
-    for y in (1..map_height).step_by(2) {
-        for x in (1..map_width).step_by(2) {
-            if !map[x as usize][y as usize].block_sight { continue ; }
+fn replace_word(line: &mut Vec<char>, cursor: &mut usize, start: usize, word: &str) {
+    line.splice(start..*cursor, word.chars());
+    *cursor = start + word.chars().count();
+}
 
-            let start = Point::new(x, y);
-            grow_maze(&mut map, start, &mut current_region, winding_percent, &mut _regions, &mut r);
-        }
+fn common_prefix(words: &[String]) -> String {
+    let mut prefix: Vec<char> = words[0].chars().collect();
+    for word in &words[1..] {
+        let shared = prefix.iter().zip(word.chars()).take_while(|(a, b)| **a == *b).count();
+        prefix.truncate(shared);
     }
+    prefix.into_iter().collect()
+}
 
-    connect_regions(&mut map, &mut _regions, current_region, extra_connector_chance, map_width, map_height, &mut r);
-
-    remove_dead_ends(&mut map, map_width, map_height);
-
-    map
-}
\ No newline at end of file
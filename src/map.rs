@@ -0,0 +1,1437 @@
+use std::cmp;
+use rand::Rng;
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::slice::Iter;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::game::{distance, PLAYER};
+use crate::object::{Object, ServerLink, SpawnTables, Spawnable, VaultSpawn};
+
+pub const MAP_WIDTH: i32 = 79;
+pub const MAP_HEIGHT: i32 = 43;
+const ROOM_MAX_SIZE: i32 = 10;
+const ROOM_MIN_SIZE: i32 = 6;
+const MAX_ROOMS: i32 = 30;
+// Largest vault template, so a vault room is never much bigger than an ordinary one
+pub const MAX_VAULT_SIZE: usize = 13;
+
+// BSP generation: areas are split until neither side is larger than the maximum, and never
+// into pieces smaller than the minimum, so every leaf has room for a room
+const BSP_MIN_LEAF: i32 = ROOM_MIN_SIZE + 4;
+const BSP_MAX_LEAF: i32 = BSP_MIN_LEAF * 2;
+
+// Cave generation: how much of the map starts out as wall, how many smoothing passes run
+// (the first few also break up large open areas), and how many times a cave that ends up
+// smaller than a third of the map is thrown away and grown again
+const CAVE_WALL_PERCENT: u32 = 45;
+const CAVE_SMOOTHING_PASSES: u32 = 7;
+const CAVE_OPEN_AREA_PASSES: u32 = 4;
+const CAVE_MAX_ATTEMPTS: u32 = 10;
+// Caves have no rooms, so monsters and items are placed per sector of the map instead
+const CAVE_SECTOR_WIDTH: i32 = 20;
+const CAVE_SECTOR_HEIGHT: i32 = 15;
+const CAVE_SECTOR_MIN_FLOOR: usize = 25;
+
+// A generator gets this many tries at a layout that can be repaired before plain rooms are carved instead
+const MAX_GENERATION_ATTEMPTS: u32 = 10;
+// Cut-off pockets smaller than this, with nothing in them that matters, are filled in rather than dug out to
+const MIN_POCKET_SIZE: usize = 4;
+
+// The generator used unless the game data or command line asks for another
+pub const DEFAULT_GENERATOR: &str = "mixed";
+// Percent chance that a server at a given depth is a corrupted sector, for the mixed generator
+const CORRUPTED_SECTOR_CHANCE: &[Transition] = &[
+    Transition { depth: 3, value: 25 },
+    Transition { depth: 5, value: 50 },
+    Transition { depth: 8, value: 75 },
+];
+
+// How many monsters and items a room may hold as the player hops deeper into the network
+const MAX_ROOM_MONSTERS: &[Transition] = &[
+    Transition { depth: 1, value: 3 },
+    Transition { depth: 4, value: 4 },
+    Transition { depth: 6, value: 5 },
+];
+const MAX_ROOM_ITEMS: &[Transition] = &[
+    Transition { depth: 1, value: 2 },
+    Transition { depth: 4, value: 3 },
+];
+
+
+// Data Types
+#[derive(Debug, Clone, Copy, PartialEq, Hash, Eq, PartialOrd, Ord)]
+pub struct Point {
+    pub x: i32,
+    pub y: i32,
+}
+
+impl Point {
+    pub fn new(x: i32, y: i32) -> Self {
+        Point { x, y }
+    }
+}
+
+/// A value that takes effect from a given server depth onwards.
+pub struct Transition {
+    pub depth: u32,
+    pub value: u32,
+}
+
+/// Returns the value that applies at `depth`: the one from the deepest transition reached.
+pub fn from_depth(table: &[Transition], depth: u32) -> u32 {
+    table
+        .iter()
+        .rev()
+        .find(|transition| depth >= transition.depth)
+        .map_or(0, |transition| transition.value)
+}
+
+pub enum Directions {
+    NORTH,
+    SOUTH,
+    EAST,
+    WEST,
+}
+
+impl Directions {
+    pub fn iterator() -> Iter<'static, Directions> {
+        static DIRECTIONS: [Directions; 4] = [Directions::NORTH, Directions::SOUTH, Directions::EAST, Directions::WEST];
+        DIRECTIONS.into_iter()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
+pub struct Tile {
+    pub blocked: bool,
+    pub block_sight: bool,
+    pub explored: bool,
+}
+
+impl Tile {
+    pub fn empty() -> Self {
+        Tile{blocked: false, block_sight: false, explored: false}
+    }
+
+    pub fn wall() -> Self {
+        Tile{blocked: true, block_sight: true, explored: false}
+    }
+}
+
+pub type Map = Vec<Vec<Tile>>;
+
+#[derive(Clone, Copy, Debug)]
+pub struct Rect {
+    pub x1: i32,
+    pub y1: i32,
+    pub x2: i32,
+    pub y2: i32,
+}
+
+impl Rect {
+    pub fn new(x: i32, y: i32, w: i32, h: i32) -> Self {
+        Rect {
+            x1: x,
+            y1: y,
+            x2: x + w,
+            y2: y + h,
+        }
+    }
+
+    pub fn center(&self) -> (i32, i32) {
+        let center_x = (self.x1 + self.x2) / 2;
+        let center_y = (self.y1 + self.y2) / 2;
+        (center_x, center_y)
+    }
+
+    pub fn intersects_with(&self, other: &Rect) -> bool {
+        (self.x1 <= other.x2) 
+            && (self.x2 >= other.x1)
+            && (self.y1 <= other.y2) 
+            && (self.y2 >= other.y1)
+    }
+}
+
+// Movement Functions
+
+pub fn get_direction(d: &Directions) -> (i32, i32) {
+    // chooses direction
+    match d {
+        Directions::NORTH => (0, -1),
+        Directions::SOUTH => (0, 1),
+        Directions::EAST => (1, 0),
+        Directions::WEST => (-1, 0),
+    }
+}
+
+pub fn out_of_bounds(x: i32, y: i32) -> bool {
+    if x > 0 && x < MAP_WIDTH && y > 0 && y < MAP_HEIGHT {
+        return false
+    }
+    true
+}
+
+/// Whether the straight line between two tiles is clear of anything that blocks sight.
+/// The tiles at either end don't count, so a monster standing in a doorway can still be seen.
+pub fn line_of_sight(map: &Map, (x0, y0): (i32, i32), (x1, y1): (i32, i32)) -> bool {
+    let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+    let (step_x, step_y) = ((x1 - x0).signum(), (y1 - y0).signum());
+    let mut error = dx + dy;
+    let (mut x, mut y) = (x0, y0);
+    while (x, y) != (x1, y1) {
+        if (x, y) != (x0, y0) && map[x as usize][y as usize].block_sight {
+            return false;
+        }
+        let doubled = 2 * error;
+        if doubled >= dy {
+            error += dy;
+            x += step_x;
+        }
+        if doubled <= dx {
+            error += dx;
+            y += step_y;
+        }
+    }
+    true
+}
+
+
+// Map Generators
+
+/// A freshly carved server, before anything has been placed on it.
+pub struct Layout {
+    pub map: Map,
+    // Rooms, cave sectors and the like: each gets its own monsters and items, spawned on its floor tiles
+    pub regions: Vec<Vec<(i32, i32)>>,
+    pub start: (i32, i32),
+    // Where the uplink to the next server goes
+    pub exit: (i32, i32),
+    // Monsters and files a vault always has, on the tiles its template puts them
+    pub fixed_spawns: Vec<((i32, i32), VaultSpawn)>,
+}
+
+/// Carves a server. Generators only shape the map; `populate` decides what goes on it.
+pub trait MapGenerator {
+    fn generate(&self, width: i32, height: i32, depth: u32, tables: &SpawnTables, rng: &mut rand_pcg::Pcg32) -> Layout;
+}
+
+/// Maps generator names, as used in the game data and on the command line, to generators.
+pub struct GeneratorRegistry {
+    generators: BTreeMap<String, Box<dyn MapGenerator>>,
+}
+
+impl GeneratorRegistry {
+    pub fn new() -> Self {
+        GeneratorRegistry { generators: BTreeMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, generator: Box<dyn MapGenerator>) {
+        self.generators.insert(name.to_string(), generator);
+    }
+
+    pub fn get(&self, name: &str) -> Option<&dyn MapGenerator> {
+        self.generators.get(name).map(|generator| generator.as_ref())
+    }
+
+    pub fn names(&self) -> Vec<&str> {
+        self.generators.keys().map(|name| name.as_str()).collect()
+    }
+}
+
+impl Default for GeneratorRegistry {
+    /// A registry holding every generator the game ships with.
+    fn default() -> Self {
+        let mut registry = GeneratorRegistry::new();
+        registry.register("rooms", Box::new(RoomsGenerator));
+        registry.register("hauberk", Box::new(HauberkGenerator));
+        registry.register("bsp", Box::new(BspGenerator));
+        registry.register("caves", Box::new(CaveGenerator));
+        registry.register(DEFAULT_GENERATOR, Box::new(MixedGenerator));
+        registry
+    }
+}
+
+/// Random rooms joined by L-shaped tunnels.
+pub struct RoomsGenerator;
+
+impl MapGenerator for RoomsGenerator {
+    fn generate(&self, width: i32, height: i32, depth: u32, tables: &SpawnTables, rng: &mut rand_pcg::Pcg32) -> Layout {
+        make_map(width, height, depth, tables, rng)
+    }
+}
+
+/// Rooms in a winding maze of corridors.
+pub struct HauberkGenerator;
+
+impl MapGenerator for HauberkGenerator {
+    fn generate(&self, width: i32, height: i32, depth: u32, tables: &SpawnTables, rng: &mut rand_pcg::Pcg32) -> Layout {
+        make_map_hauberk(width, height, depth, tables, rng)
+    }
+}
+
+/// Rooms spread evenly by binary space partitioning.
+pub struct BspGenerator;
+
+impl MapGenerator for BspGenerator {
+    fn generate(&self, width: i32, height: i32, _depth: u32, _tables: &SpawnTables,
+                rng: &mut rand_pcg::Pcg32) -> Layout {
+        make_map_bsp(width, height, rng)
+    }
+}
+
+/// Nothing but corrupted sectors.
+pub struct CaveGenerator;
+
+impl MapGenerator for CaveGenerator {
+    fn generate(&self, width: i32, height: i32, _depth: u32, _tables: &SpawnTables,
+                rng: &mut rand_pcg::Pcg32) -> Layout {
+        make_map_caves(width, height, rng)
+    }
+}
+
+/// Rooms and corridors of either kind, with more and more corrupted sectors deeper in the network.
+pub struct MixedGenerator;
+
+impl MapGenerator for MixedGenerator {
+    fn generate(&self, width: i32, height: i32, depth: u32, tables: &SpawnTables, rng: &mut rand_pcg::Pcg32) -> Layout {
+        let corrupted_chance = from_depth(CORRUPTED_SECTOR_CHANCE, depth);
+        if corrupted_chance > 0 && rng.gen_range(0, 100) < corrupted_chance {
+            make_map_caves(width, height, rng)
+        } else if rng.gen() {
+            make_map_bsp(width, height, rng)
+        } else {
+            make_map_hauberk(width, height, depth, tables, rng)
+        }
+    }
+}
+
+/// Generates a layout and repairs it, trying again from scratch if it is beyond repair. A generator
+/// that keeps failing is given up on for rooms without vaults, which are joined up as they are carved
+/// and so should always pass repair.
+pub fn generate_layout(generator: &dyn MapGenerator, width: i32, height: i32, depth: u32, tables: &SpawnTables,
+                       rng: &mut rand_pcg::Pcg32) -> Layout {
+    for _ in 0..MAX_GENERATION_ATTEMPTS {
+        let mut layout = generator.generate(width, height, depth, tables, rng);
+        if repair_layout(&mut layout) {
+            return layout;
+        }
+    }
+
+    // Should repair ever fail on these too, a stray pocket is still better than no server at all
+    let mut layout = make_map(width, height, depth, &SpawnTables::default(), rng);
+    repair_layout(&mut layout);
+    layout
+}
+
+/// Enforces what every generator has to guarantee: the start, the exit and every fixed spawn are
+/// on floor, and every floor tile can be walked to from the start. A cut-off pocket is joined to the
+/// rest of the map by the shortest tunnel, unless it is tiny and empty, in which case it is filled in.
+/// Returns false if the layout is beyond repair.
+pub fn repair_layout(layout: &mut Layout) -> bool {
+    let (width, height) = (layout.map.len() as i32, layout.map[0].len() as i32);
+    let on_floor = |map: &Map, (x, y): (i32, i32)| {
+        x > 0 && x < width - 1 && y > 0 && y < height - 1 && !map[x as usize][y as usize].blocked
+    };
+    if !on_floor(&layout.map, layout.start)
+        || !on_floor(&layout.map, layout.exit)
+        || layout.fixed_spawns.iter().any(|&(tile, _)| !on_floor(&layout.map, tile)) {
+        return false;
+    }
+
+    loop {
+        let mut reached = vec![vec![false; height as usize]; width as usize];
+        for (x, y) in flood_fill(&layout.map, layout.start.0, layout.start.1) {
+            reached[x as usize][y as usize] = true;
+        }
+        let cut_off = (0..width)
+            .flat_map(|x| (0..height).map(move |y| (x, y)))
+            .find(|&(x, y)| !layout.map[x as usize][y as usize].blocked && !reached[x as usize][y as usize]);
+        let (x, y) = match cut_off {
+            Some(tile) => tile,
+            None => break,
+        };
+
+        let pocket = flood_fill(&layout.map, x, y);
+        let worth_keeping = pocket.len() >= MIN_POCKET_SIZE
+            || pocket.contains(&layout.exit)
+            || layout.fixed_spawns.iter().any(|(tile, _)| pocket.contains(tile));
+        if worth_keeping {
+            if !dig_tunnel(&mut layout.map, &pocket, &reached) {
+                return false;
+            }
+        } else {
+            for &(x, y) in &pocket {
+                layout.map[x as usize][y as usize] = Tile::wall();
+            }
+        }
+    }
+
+    let map = &layout.map;
+    for region in layout.regions.iter_mut() {
+        region.retain(|&(x, y)| !map[x as usize][y as usize].blocked);
+    }
+    true
+}
+
+/// Digs the shortest tunnel, never through the outer wall, from a pocket to any tile in `reached`.
+fn dig_tunnel(map: &mut Map, pocket: &[(i32, i32)], reached: &[Vec<bool>]) -> bool {
+    let (width, height) = (map.len() as i32, map[0].len() as i32);
+    let mut seen = vec![vec![false; height as usize]; width as usize];
+    let mut came_from = HashMap::new();
+    let mut frontier = VecDeque::new();
+    for &(x, y) in pocket {
+        seen[x as usize][y as usize] = true;
+        frontier.push_back((x, y));
+    }
+
+    while let Some((x, y)) = frontier.pop_front() {
+        if reached[x as usize][y as usize] {
+            let mut tile = (x, y);
+            while let Some(&previous) = came_from.get(&tile) {
+                map[tile.0 as usize][tile.1 as usize] = Tile::empty();
+                tile = previous;
+            }
+            return true;
+        }
+        for d in Directions::iterator() {
+            let (dx, dy) = get_direction(d);
+            let (nx, ny) = (x + dx, y + dy);
+            if nx <= 0 || nx >= width - 1 || ny <= 0 || ny >= height - 1 || seen[nx as usize][ny as usize] {
+                continue;
+            }
+            seen[nx as usize][ny as usize] = true;
+            came_from.insert((nx, ny), (x, y));
+            frontier.push_back((nx, ny));
+        }
+    }
+    false
+}
+
+/// Puts the player, the monsters and items of every region, and the links onto a carved server.
+pub fn populate(layout: &Layout, objects: &mut Vec<Object>, rng: &mut rand_pcg::Pcg32, depth: u32, tables: &SpawnTables) {
+    let (start_x, start_y) = layout.start;
+    objects[PLAYER].set_pos(start_x, start_y);
+    for region in &layout.regions {
+        place_objects(region, objects, rng, depth, tables);
+    }
+    // Vault names were checked against the tables when the game data was loaded
+    for &((x, y), ref spawn) in &layout.fixed_spawns {
+        match spawn {
+            VaultSpawn::Monster(_) if is_occupied(objects, x, y) => {}
+            VaultSpawn::Monster(name) => if let Some(monster_def) = tables.monsters.get(name) {
+                let monster = Object::new_monster(x, y, name, monster_def, get_new_object_id(objects));
+                objects.push(monster);
+            },
+            VaultSpawn::Item(name) => if let Some(item_def) = tables.items.get(name) {
+                let object = Object::new_item(x, y, name, item_def, get_new_object_id(objects));
+                objects.push(object);
+            },
+        }
+    }
+    place_links(layout.exit, objects, depth);
+}
+
+// Map Functions
+
+pub fn make_map(width: i32, height: i32, depth: u32, tables: &SpawnTables, rng: &mut rand_pcg::Pcg32) -> Layout {
+    // fill map with "blocked" tiles
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+    // map algo
+    let mut rooms = vec![];
+    let mut vaults = vec![];
+    for _ in 0..MAX_ROOMS {
+        let mut w = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+        let mut h = rng.gen_range(ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+        // A vault room is its template, a ring of floor to walk around it, and the room's own wall
+        let vault = roll_vault(tables, depth, rng);
+        if let Some(vault) = &vault {
+            w = vault.width() + 3;
+            h = vault.height() + 3;
+        }
+        let x = rng.gen_range(0, width - w);
+        let y = rng.gen_range(0, height - h);
+
+        let new_room = Rect::new(x, y, w, h);
+
+        let failed = rooms
+            .iter()
+            .any(|other_room| new_room.intersects_with(other_room));
+        
+        if !failed {
+            if let Some(vault) = vault {
+                create_room(new_room, &mut map);
+                vaults.push((new_room.x1 + 2, new_room.y1 + 2, vault));
+            } else if rng.gen::<f32>() < 0.15 {
+                create_circle_room(new_room, &mut map);
+            } else {
+                create_room(new_room, &mut map);
+            }
+            let room_center = new_room.center();
+
+            if !rooms.is_empty() {
+                let prev_center = rooms[rooms.len() - 1].center();
+                if rng.gen() {
+                    create_h_tunnel(prev_center.0, room_center.0, prev_center.1, &mut map);
+                    create_v_tunnel(prev_center.1, room_center.1, room_center.0, &mut map);
+                } else {
+                    create_v_tunnel(prev_center.1, room_center.1, prev_center.0, &mut map);
+                    create_h_tunnel(prev_center.0, room_center.0, room_center.1, &mut map);
+                }
+            }
+
+            rooms.push(new_room);
+        }
+    }
+
+    // Last, so no tunnel dug afterwards can cut through a vault's walls
+    let mut fixed_spawns = vec![];
+    for (x, y, vault) in &vaults {
+        stamp_vault(vault, *x, *y, &mut map, &mut fixed_spawns);
+    }
+    rooms_layout(map, &rooms, fixed_spawns)
+}
+
+/// Starts the player in the first room and puts the uplink in the last.
+fn rooms_layout(map: Map, rooms: &[Rect], fixed_spawns: Vec<((i32, i32), VaultSpawn)>) -> Layout {
+    // Round rooms leave their corners solid, so only what was actually carved is a spawn point
+    let regions = rooms
+        .iter()
+        .map(|room| {
+            (room.x1..room.x2)
+                .flat_map(|x| (room.y1..room.y2).map(move |y| (x, y)))
+                .filter(|&(x, y)| !map[x as usize][y as usize].blocked)
+                .collect()
+        })
+        .collect::<Vec<Vec<(i32, i32)>>>();
+    // A vault may have a wall where the room's center is
+    let floor_near_center = |room_id: usize| {
+        let (x, y) = rooms[room_id].center();
+        if map[x as usize][y as usize].blocked { regions[room_id][0] } else { (x, y) }
+    };
+    let start = floor_near_center(0);
+    let exit = floor_near_center(rooms.len() - 1);
+    Layout { map, regions, start, exit, fixed_spawns }
+}
+
+/// A vault picked for a room, turned and flipped the way it will be stamped.
+struct Vault<'a> {
+    rows: Vec<Vec<char>>,
+    legend: &'a BTreeMap<char, VaultSpawn>,
+}
+
+impl<'a> Vault<'a> {
+    fn width(&self) -> i32 {
+        self.rows[0].len() as i32
+    }
+
+    fn height(&self) -> i32 {
+        self.rows.len() as i32
+    }
+}
+
+/// Rolls the tables' vault chance for one room and, if it comes up, picks a vault for `depth`.
+fn roll_vault<'a>(tables: &'a SpawnTables, depth: u32, rng: &mut rand_pcg::Pcg32) -> Option<Vault<'a>> {
+    if tables.vault_chance == 0 || rng.gen_range(0, 100) >= tables.vault_chance {
+        return None;
+    }
+
+    let (_, vault_def) = choose_spawn(&tables.vaults, depth, rng)?;
+    let mut rows: Vec<Vec<char>> = vault_def.template.iter().map(|row| row.chars().collect()).collect();
+    if vault_def.rotate {
+        for _ in 0..rng.gen_range(0, 4) {
+            // A quarter turn clockwise: each column, read bottom to top, becomes a row
+            rows = (0..rows[0].len()).map(|x| rows.iter().rev().map(|row| row[x]).collect()).collect();
+        }
+    }
+    if vault_def.mirror && rng.gen() {
+        for row in rows.iter_mut() {
+            row.reverse();
+        }
+    }
+    Some(Vault { rows, legend: &vault_def.legend })
+}
+
+/// Writes a vault's walls and floor with its top left corner at `(x, y)`, and notes what it always spawns.
+fn stamp_vault(vault: &Vault, x: i32, y: i32, map: &mut Map, fixed_spawns: &mut Vec<((i32, i32), VaultSpawn)>) {
+    for (dy, row) in vault.rows.iter().enumerate() {
+        for (dx, tile) in row.iter().enumerate() {
+            let (tile_x, tile_y) = (x + dx as i32, y + dy as i32);
+            map[tile_x as usize][tile_y as usize] = if *tile == '#' { Tile::wall() } else { Tile::empty() };
+            if let Some(spawn) = vault.legend.get(tile) {
+                fixed_spawns.push(((tile_x, tile_y), spawn.clone()));
+            }
+        }
+    }
+}
+
+/// Splits the map into a binary tree of areas, puts a room in every leaf and joins each pair of
+/// siblings with a tunnel, so rooms are spread evenly over the whole map.
+pub fn make_map_bsp(width: i32, height: i32, rng: &mut rand_pcg::Pcg32) -> Layout {
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+    let mut rooms = vec![];
+    // One tile short on each side so the outer wall is never carved
+    let whole_map = Rect::new(0, 0, width - 1, height - 1);
+    carve_bsp(whole_map, &mut map, rng, &mut rooms);
+
+    rooms_layout(map, &rooms, Vec::new())
+}
+
+/// Carves the rooms and tunnels for one node of the tree and returns a floor tile inside it,
+/// for the tunnel that joins it to its sibling.
+fn carve_bsp(area: Rect, map: &mut Map, rng: &mut rand_pcg::Pcg32, rooms: &mut Vec<Rect>) -> (i32, i32) {
+    let width = area.x2 - area.x1;
+    let height = area.y2 - area.y1;
+
+    if width <= BSP_MAX_LEAF && height <= BSP_MAX_LEAF {
+        let w = rng.gen_range(ROOM_MIN_SIZE, cmp::min(ROOM_MAX_SIZE, width) + 1);
+        let h = rng.gen_range(ROOM_MIN_SIZE, cmp::min(ROOM_MAX_SIZE, height) + 1);
+        let x = rng.gen_range(area.x1, area.x2 - w + 1);
+        let y = rng.gen_range(area.y1, area.y2 - h + 1);
+        let room = Rect::new(x, y, w, h);
+        if rng.gen::<f32>() < 0.15 {
+            create_circle_room(room, map);
+        } else {
+            create_room(room, map);
+        }
+        rooms.push(room);
+        return room.center();
+    }
+
+    // Cut across the longer side, unless the area is close to square
+    let split_vertically = if width > height * 5 / 4 {
+        true
+    } else if height > width * 5 / 4 {
+        false
+    } else {
+        rng.gen()
+    };
+    // ...as long as both halves still fit a leaf
+    let split_vertically = (split_vertically && width >= BSP_MIN_LEAF * 2) || height < BSP_MIN_LEAF * 2;
+    let (first, second) = if split_vertically {
+        let split = rng.gen_range(area.x1 + BSP_MIN_LEAF, area.x2 - BSP_MIN_LEAF + 1);
+        (Rect::new(area.x1, area.y1, split - area.x1, height), Rect::new(split, area.y1, area.x2 - split, height))
+    } else {
+        let split = rng.gen_range(area.y1 + BSP_MIN_LEAF, area.y2 - BSP_MIN_LEAF + 1);
+        (Rect::new(area.x1, area.y1, width, split - area.y1), Rect::new(area.x1, split, width, area.y2 - split))
+    };
+
+    let (x1, y1) = carve_bsp(first, map, rng, rooms);
+    let (x2, y2) = carve_bsp(second, map, rng, rooms);
+    if rng.gen() {
+        create_h_tunnel(x1, x2, y1, map);
+        create_v_tunnel(y1, y2, x2, map);
+    } else {
+        create_v_tunnel(y1, y2, x1, map);
+        create_h_tunnel(x1, x2, y2, map);
+    }
+
+    if rng.gen() { (x1, y1) } else { (x2, y2) }
+}
+
+fn create_room(room: Rect, map: &mut Map) {
+    for x in (room.x1 + 1)..room.x2 {
+        for y in (room.y1 + 1)..room.y2 {
+            map[x as usize][y as usize] = Tile::empty();
+        }
+    }
+}
+
+fn create_circle_room(room: Rect, map: &mut Map) {
+    // Code from @Agka on roguelikedev-help Discord channel - many thanks!
+    let rdx = room.x2 - room.x1;
+    let rdy = room.y2 - room.y1;
+
+    let div_val = cmp::min(rdx, rdy);
+
+    let radius: f32 = (div_val as f32 / 2.0) - 1.0;
+    let rad_floor: i32 = radius.floor() as i32;
+    let radsqr = radius.floor().powf(2.0) as i32;
+    let (center_x, center_y) = room.center();
+
+    let x_ratio = cmp::max(rdx / rdy, 1);
+    let y_ratio = cmp::max(rdy / rdx, 1);
+
+    for x in center_x - rad_floor - 1..center_x + rad_floor + 1 {
+        for y in center_y - rad_floor - 1..center_y + rad_floor + 1 {
+            let dx = (x - center_x) / x_ratio;
+            let dy = (y - center_y) / y_ratio;
+            let distsqr = dx.pow(2) + dy.pow(2);
+            if distsqr < radsqr {
+                map[x as usize][y as usize] = Tile::empty();
+            }
+        }
+    }
+}
+
+fn create_h_tunnel(x1: i32, x2: i32, y: i32, map: &mut Map) {
+    for x in cmp::min(x1, x2)..(cmp::max(x1, x2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+fn create_v_tunnel(y1: i32, y2: i32, x: i32, map: &mut Map) {
+    for y in cmp::min(y1, y2)..(cmp::max(y1, y2) + 1) {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+}
+
+/// Whether the player or a monster already stands on the tile.
+fn is_occupied(objects: &[Object], x: i32, y: i32) -> bool {
+    objects.iter().any(|object| object.blocks && object.pos() == (x, y))
+}
+
+/// Picks an entry allowed at `depth`, weighted by each definition's spawn weight.
+fn choose_spawn<'a, T: Spawnable>(table: &'a BTreeMap<String, T>, depth: u32, rng: &mut rand_pcg::Pcg32) -> Option<(&'a String, &'a T)> {
+    let candidates: Vec<_> = table
+        .iter()
+        .filter(|(_, def)| def.min_depth() <= depth && def.spawn_weight() > 0)
+        .collect();
+    let total_weight: u32 = candidates.iter().map(|(_, def)| def.spawn_weight()).sum();
+    if total_weight == 0 {
+        return None;
+    }
+
+    let mut roll = rng.gen_range(0, total_weight);
+    for (name, def) in candidates {
+        if roll < def.spawn_weight() {
+            return Some((name, def));
+        }
+        roll -= def.spawn_weight();
+    }
+    None
+}
+
+/// Puts the uplink to the next server at `exit` and, below the first server,
+/// a backlink under the player's arrival point.
+fn place_links(exit: (i32, i32), objects: &mut Vec<Object>, depth: u32) {
+    let (x, y) = exit;
+    let uplink = Object::new_link(x, y, ServerLink::Uplink, get_new_object_id(objects));
+    objects.push(uplink);
+
+    if depth > 1 {
+        let (x, y) = objects[PLAYER].pos();
+        let backlink = Object::new_link(x, y, ServerLink::Backlink, get_new_object_id(objects));
+        objects.push(backlink);
+    }
+}
+
+pub fn reveal_map(map: &mut Map) {
+    for x in 0..MAP_WIDTH {
+        for y in 0..MAP_HEIGHT {
+            map[x as usize][y as usize].explored = true;
+        }
+    }
+}
+
+/// One more than the highest id in use. Objects are swap-removed and pushed in any order,
+/// so the last one isn't necessarily the newest.
+pub fn get_new_object_id(objects: &[Object]) -> i32 {
+    objects.iter().map(|object| object.id).max().map_or(1, |id| id + 1)
+}
+
+// The Hauberk Map Generater
+
+pub fn make_map_hauberk(width: i32, height: i32, depth: u32, tables: &SpawnTables, r: &mut rand_pcg::Pcg32) -> Layout {
+
+    let num_room_tries = 100;
+    let extra_connector_chance = 25;
+    //let room_extra_size = 0;
+    let winding_percent = 10;
+
+    let mut current_region: i32 = -1;
+
+    let mut map_width: i32 = width;
+    let mut map_height: i32 = height;
+
+    if map_width % 2 == 0 {
+        map_width -= 1;
+    }
+
+    if map_height % 2 == 0 {
+        map_height -= 1;
+    }
+
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+
+    type VecRegion = Vec<Vec<i32>>;
+
+    let mut _regions = vec![vec![0; height as usize]; width as usize];
+
+    //fn on_decorate_room(room: Rect) {}
+
+    fn pcg_choose<T>(v: &Vec<T>, r: &mut rand_pcg::Pcg32) -> Option<usize> {
+        if v.is_empty() {
+            return None
+        } else {
+            let vec_length: f64 = v.len() as f64;
+            let num_digits = vec_length.log10().round() as u32;
+            let modulo: u32 = (10 as u32).pow(num_digits);
+            let mut index_option = r.next_u32() % modulo;
+            while v.len()-1 < index_option as usize {
+                index_option = r.next_u32() % modulo;
+            }
+            return Some(index_option as usize)
+        }
+    }
+
+    fn grow_maze(map: &mut Map, start: Point, current_region: &mut i32, winding_percent: i32, _regions: &mut VecRegion,
+                 map_width: i32, map_height: i32, r: &mut rand_pcg::Pcg32) {
+        let mut cells = Vec::new();
+        let mut last_dir = (0, 0);
+
+        start_region(current_region);
+        carve(&start, map, _regions, current_region);
+
+        cells.push(start);
+
+        while !cells.is_empty() {
+            let cell = cells.last().unwrap();
+
+            let mut unmade_cells = Vec::new();
+            for d in Directions::iterator() {
+                let (dx, dy) = get_direction(d);
+                let target_pos: Point = Point::new(cell.x + dx, cell.y + dy);
+                if can_carve(map, target_pos, d, map_width, map_height) {
+                    unmade_cells.push((dx, dy));
+                }
+            }
+
+            if !unmade_cells.is_empty() {
+                let mut dir = (0, 0);
+                if unmade_cells.contains(&last_dir) && (r.next_u32() % 100) > winding_percent as u32 {
+                    dir = last_dir;
+                } else {
+                    let index_option = pcg_choose(&unmade_cells, r);
+                    match index_option {
+                        Some(i) => {dir = unmade_cells[i];}
+                        None => ()
+                    }
+                    /* let dir_choice = unmade_cells.choose(&mut rand::thread_rng());
+                    match dir_choice {
+                        Some(d) => {dir = *d;}
+                        None => ()
+                    } */
+                }
+
+                let close_pos = Point::new(cell.x + dir.0, cell.y + dir.1);
+                let far_pos = Point::new(cell.x + (dir.0 * 2), cell.y + (dir.1 * 2));
+                carve(&close_pos, map, _regions, current_region);
+                carve(&far_pos, map, _regions, current_region);
+
+                cells.push(far_pos);
+
+                last_dir = dir;
+
+            } else {
+                cells.pop();
+                last_dir = (0, 0);
+            }
+        }
+
+    }
+
+    fn pcg_range(r: &mut rand_pcg::Pcg32, min: i32, max: i32) -> i32 {
+        let num_digits = (max as f32).log10().round() as u32;
+        let modulo: u32 = (10 as u32).pow(num_digits);
+
+        let mut result: i32 = max + 1;
+        while !(result > min && result < max) {
+            let pcg = r.next_u32() % modulo;
+            result = pcg as i32;
+        }
+        
+        result
+    }
+
+    fn add_rooms<'a>(map: &mut Map, tries: i32, current_region: &mut i32, _regions: &mut VecRegion,
+                     map_width: i32, map_height: i32, r: &mut rand_pcg::Pcg32, depth: u32, tables: &'a SpawnTables,
+                     vaults: &mut Vec<(i32, i32, Vault<'a>)>) -> Vec<Rect> {
+        let mut rooms = Vec::new();
+        for _ in 0..=tries {
+            let mut w = pcg_range(r, ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+            let mut h = pcg_range(r, ROOM_MIN_SIZE, ROOM_MAX_SIZE + 1);
+            // A vault room is its template and a ring of floor the maze can connect to
+            let vault = roll_vault(tables, depth, r);
+            if let Some(vault) = &vault {
+                w = vault.width() + 2;
+                h = vault.height() + 2;
+            }
+            let x = (pcg_range(r, 0, map_width - w - 1) / 2)* 2 + 1;
+            let y = (pcg_range(r, 0, map_height - h - 1) / 2) * 2 + 1;
+
+            let new_room = Rect::new(x, y, w, h);
+
+            let failed = rooms
+            .iter()
+            .any(|other_room| new_room.intersects_with(other_room));
+
+            if !failed {
+                rooms.push(new_room);
+                start_region(current_region);
+                create_room_hauberk(&new_room, map, _regions, current_region);
+                // Carved as plain floor for now so the maze and connectors treat it as any other room
+                if let Some(vault) = vault {
+                    vaults.push((new_room.x1 + 1, new_room.y1 + 1, vault));
+                }
+            }
+        }
+
+        rooms
+    }
+
+    fn connect_regions(map: &mut Map, _regions: &mut VecRegion, current_region: i32, extra_chance: i32,
+                       map_width: i32, map_height: i32, r: &mut rand_pcg::Pcg32) {
+                           
+        let mut connector_regions = HashMap::new();
+
+        for x in 1..map_width-1 {
+            for y in 1..map_height-1 {
+                if !map[x as usize][y as usize].block_sight { continue; }
+
+                let mut regions = Vec::new();
+                for d in Directions::iterator() {
+                    let (dx, dy) = get_direction(d);
+                    let region = _regions[(x + dx) as usize][(y + dy) as usize];
+                    regions.push(region);
+                }
+
+                if regions.len() < 2 { continue; }
+
+                connector_regions.insert(Point::new(x, y), regions);
+            }
+        }
+
+        let mut connectors: Vec<_> = connector_regions.keys().collect();
+        connectors.sort();
+        //println!("{:?}", connectors);
+
+        let mut merged = HashMap::new();
+        let mut open_regions = Vec::new();
+
+        //println!("{}", current_region);
+
+        for i in 0..current_region {
+            merged.insert(i, i);
+            open_regions.push(i);
+        }
+
+        //println!("{:?}", open_regions);
+
+        while !open_regions.is_empty() {
+            let connector_idx = pcg_choose(&connectors, r);
+            let mut regions = Vec::new();
+            match connector_idx {
+                Some(c) => {
+                    let connector = connectors[c];
+                    add_junction(connector, map/*, _regions, current_region, r*/);
+                    if connector_regions.contains_key(connector) {
+                        for region in &connector_regions[connector] {
+                            if merged.contains_key(&region) {
+                                let actual_region = merged[&region];
+                                regions.push(actual_region);
+                            }
+                        }
+                    }
+
+                    let dest = regions.first();
+                    let mut dest_region = 0;
+                    match dest {
+                        Some(region) => { dest_region = *region; }
+                        None => ()
+                    }
+
+                    let sources: Vec<_> = regions[1..].iter().collect();
+
+                    for i in 0..current_region {
+                        if sources.contains(&&merged[&i]) {
+                            if merged.contains_key(&i) {
+                                merged.remove(&i);
+                                merged.insert(i, dest_region);
+                            }
+                        }
+                    }
+
+                    for s in sources {
+                        let index = open_regions.iter().position(|x| *x == *s);
+                        match index {
+                            Some(n) => { 
+                                if open_regions.len() >= n {
+                                    open_regions.remove(n); 
+                                }
+                            }
+                            None => ()
+                        }
+                    }
+
+                    // Only depends on the chosen connector, so look it up once rather than for every position
+                    let mut local_regions = Vec::new();
+                    for r in &connector_regions[connector] {
+                        if merged.contains_key(&r) {
+                            let region_actual = merged[&r];
+                            local_regions.push(region_actual);
+                        }
+                    }
+
+                    let mut to_be_removed = Vec::new();
+                    for pos in &connectors {
+                        if distance(connector.x-pos.x, connector.y-pos.y) < 2.0 {
+                            to_be_removed.push(*pos);
+                            continue;
+                        }
+
+                        if local_regions.len() > 1 { continue; }
+
+                        let new_junc = r.next_u32() % 100;
+                        //let new_junc: u32 = 1;
+                        if new_junc < extra_chance as u32 {
+                            add_junction(pos, map/*, _regions, current_region, r*/);
+                        }
+
+                        if local_regions.len() == 1 {
+                            to_be_removed.push(*pos);
+                        }
+                    }
+
+                    connectors.retain(|&x| !to_be_removed.contains(&x));
+                }
+                // Some seeds use up every connector before all regions are merged
+                None => break
+            }
+
+        }
+
+        
+    }
+
+    fn add_junction(pos: &Point, map: &mut Map/*, _regions: &mut VecRegion, current_region: i32, r: &mut rand_pcg::Pcg32*/) {
+        //println!("Adding junction at: ({}, {})", pos.x, pos.y);
+        map[pos.x as usize][pos.y as usize] = Tile::empty();
+    }
+
+    fn remove_dead_ends(map: &mut Map, map_width: i32, map_height: i32) {
+        let mut done = false;
+
+        while !done {
+            done = true;
+
+            for x in 1..map_width {
+                for y in 1..map_height {
+                    if map[x as usize][y as usize].block_sight { continue; }
+
+                    let mut exits = 0;
+                    for d in Directions::iterator() {
+                        let (dx, dy) = get_direction(d);
+                        let (target_x, target_y) = (x + dx, y + dy);
+                        if !map[target_x as usize][target_y as usize].block_sight { exits += 1; }
+                    }
+
+                    if exits != 1 { continue; }
+
+                    done = false;
+                    map[x as usize][y as usize] = Tile::wall();
+                }
+            }
+        }
+
+    }
+
+    fn can_carve(map: &mut Map, pos: Point, d: &Directions, map_width: i32, map_height: i32) -> bool {
+        let (dx, dy) = get_direction(d);
+        let test_point = (pos.x + (dx*3), pos.y + (dy*3));
+        if test_point.0 <= 0 || test_point.0 >= map_width || test_point.1 <= 0 || test_point.1 >= map_height {
+            return false
+        }
+
+        let (target_x, target_y) = (pos.x + dx, pos.y + dy);
+
+        return map[target_x as usize][target_y as usize].block_sight;
+    }
+
+    fn start_region(i: &mut i32) {
+        *i = *i + 1;
+    }
+
+    fn carve(pos: &Point, map: &mut Map, _regions: &mut VecRegion, current_region: &mut i32) {
+        map[pos.x as usize][pos.y as usize] = Tile::empty();
+        //println!("Made ({}, {}) a floor tile", pos.x, pos.y);
+        _regions[pos.x as usize][pos.y as usize] = *current_region;
+    }
+
+    fn create_room_hauberk(room: &Rect, map: &mut Map, _regions: &mut VecRegion, current_region: &mut i32) {
+        for x in room.x1..room.x2 {
+            for y in room.y1..room.y2 {
+                let target_point = Point::new(x, y);
+                carve(&target_point, map, _regions, current_region);
+            }
+        }
+    }
+
+    let mut vaults = Vec::new();
+    let rooms = add_rooms(&mut map, num_room_tries, &mut current_region, &mut _regions, map_width, map_height, r,
+                          depth, tables, &mut vaults);
+
+    for y in (1..map_height).step_by(2) {
+        for x in (1..map_width).step_by(2) {
+            if !map[x as usize][y as usize].block_sight { continue ; }
+
+            let start = Point::new(x, y);
+            grow_maze(&mut map, start, &mut current_region, winding_percent, &mut _regions, map_width, map_height, r);
+        }
+    }
+
+    connect_regions(&mut map, &mut _regions, current_region, extra_connector_chance, map_width, map_height, r);
+
+    remove_dead_ends(&mut map, map_width, map_height);
+
+    let mut fixed_spawns = Vec::new();
+    for (x, y, vault) in &vaults {
+        stamp_vault(vault, *x, *y, &mut map, &mut fixed_spawns);
+    }
+    rooms_layout(map, &rooms, fixed_spawns)
+}
+
+// The Corrupted Sector (Cave) Generator
+
+/// Grows an organic cave with cellular automata. Only the largest connected cave is kept, so
+/// everything on the server can be reached from where the player starts.
+pub fn make_map_caves(width: i32, height: i32, rng: &mut rand_pcg::Pcg32) -> Layout {
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+    let mut cave = Vec::new();
+    for _ in 0..CAVE_MAX_ATTEMPTS {
+        map = grow_cave(width, height, rng);
+        cave = cull_pockets(&mut map);
+        if cave.len() >= (width * height / 3) as usize {
+            break;
+        }
+    }
+
+    let mut regions = Vec::new();
+    for sector_x in (0..width).step_by(CAVE_SECTOR_WIDTH as usize) {
+        for sector_y in (0..height).step_by(CAVE_SECTOR_HEIGHT as usize) {
+            let sector = Rect::new(sector_x, sector_y, CAVE_SECTOR_WIDTH, CAVE_SECTOR_HEIGHT);
+            let floor: Vec<(i32, i32)> = cave
+                .iter()
+                .cloned()
+                .filter(|&(x, y)| x >= sector.x1 && x < sector.x2 && y >= sector.y1 && y < sector.y2)
+                .collect();
+            if floor.len() >= CAVE_SECTOR_MIN_FLOOR {
+                regions.push(floor);
+            }
+        }
+    }
+
+    let start = cave[rng.gen_range(0, cave.len())];
+    // The last tile a flood fill reaches is as far from the start as the cave allows
+    let exit = *flood_fill(&map, start.0, start.1).last().unwrap();
+    Layout { map, regions, start, exit, fixed_spawns: Vec::new() }
+}
+
+/// Scatters walls at random, then smooths them into caves: a tile becomes wall when most of
+/// its neighbours are, and during the first passes also when it sits in a wide open area.
+fn grow_cave(width: i32, height: i32, rng: &mut rand_pcg::Pcg32) -> Map {
+    let mut map = vec![vec![Tile::wall(); height as usize]; width as usize];
+    for x in 1..width - 1 {
+        for y in 1..height - 1 {
+            if rng.gen_range(0, 100) >= CAVE_WALL_PERCENT {
+                map[x as usize][y as usize] = Tile::empty();
+            }
+        }
+    }
+
+    for pass in 0..CAVE_SMOOTHING_PASSES {
+        let mut next = map.clone();
+        for x in 1..width - 1 {
+            for y in 1..height - 1 {
+                let walls_near = count_walls(&map, x, y, 1);
+                let open_area = pass < CAVE_OPEN_AREA_PASSES && count_walls(&map, x, y, 2) <= 2;
+                next[x as usize][y as usize] = if walls_near >= 5 || open_area { Tile::wall() } else { Tile::empty() };
+            }
+        }
+        map = next;
+    }
+
+    map
+}
+
+/// Walls within `radius` of a tile, the tile itself included. Anything off the map counts as wall.
+fn count_walls(map: &Map, x: i32, y: i32, radius: i32) -> u32 {
+    let (width, height) = (map.len() as i32, map[0].len() as i32);
+    let mut walls = 0;
+    for nx in x - radius..=x + radius {
+        for ny in y - radius..=y + radius {
+            if nx < 0 || nx >= width || ny < 0 || ny >= height || map[nx as usize][ny as usize].blocked {
+                walls += 1;
+            }
+        }
+    }
+    walls
+}
+
+/// Walls up every cave except the largest and returns the floor tiles that are left.
+fn cull_pockets(map: &mut Map) -> Vec<(i32, i32)> {
+    let (width, height) = (map.len() as i32, map[0].len() as i32);
+    let mut seen = vec![vec![false; height as usize]; width as usize];
+    let mut largest: Vec<(i32, i32)> = Vec::new();
+    for x in 0..width {
+        for y in 0..height {
+            if seen[x as usize][y as usize] || map[x as usize][y as usize].blocked {
+                continue;
+            }
+            let pocket = flood_fill(map, x, y);
+            for &(px, py) in &pocket {
+                seen[px as usize][py as usize] = true;
+            }
+            if pocket.len() > largest.len() {
+                largest = pocket;
+            }
+        }
+    }
+
+    for column in map.iter_mut() {
+        for tile in column.iter_mut() {
+            *tile = Tile::wall();
+        }
+    }
+    for &(x, y) in &largest {
+        map[x as usize][y as usize] = Tile::empty();
+    }
+    largest
+}
+
+/// Every floor tile that can be walked to from `(x, y)`, nearest first.
+fn flood_fill(map: &Map, x: i32, y: i32) -> Vec<(i32, i32)> {
+    let (width, height) = (map.len() as i32, map[0].len() as i32);
+    let mut reached = vec![vec![false; height as usize]; width as usize];
+    let mut tiles = Vec::new();
+    let mut frontier = VecDeque::new();
+    reached[x as usize][y as usize] = true;
+    frontier.push_back((x, y));
+
+    while let Some((x, y)) = frontier.pop_front() {
+        tiles.push((x, y));
+        for d in Directions::iterator() {
+            let (dx, dy) = get_direction(d);
+            let (nx, ny) = (x + dx, y + dy);
+            if nx < 0 || nx >= width || ny < 0 || ny >= height
+                || reached[nx as usize][ny as usize] || map[nx as usize][ny as usize].blocked {
+                continue;
+            }
+            reached[nx as usize][ny as usize] = true;
+            frontier.push_back((nx, ny));
+        }
+    }
+    tiles
+}
+
+/// Spawns a region's monsters and items, each on one of its `floor` tiles.
+fn place_objects(floor: &[(i32, i32)], objects: &mut Vec<Object>, rng: &mut rand_pcg::Pcg32, depth: u32,
+                 tables: &SpawnTables) {
+    if floor.is_empty() {
+        return;
+    }
+
+    let max_monsters = from_depth(MAX_ROOM_MONSTERS, depth) as i32;
+    let num_monsters = rng.gen_range(0, max_monsters + 1);
+    for _ in 0..num_monsters {
+        let (x, y) = floor[rng.gen_range(0, floor.len())];
+        if is_occupied(objects, x, y) {
+            continue;
+        }
+        if let Some((name, monster_def)) = choose_spawn(&tables.monsters, depth, rng) {
+            let monster = Object::new_monster(x, y, name, monster_def, get_new_object_id(objects));
+            objects.push(monster);
+        }
+    }
+
+    let max_items = from_depth(MAX_ROOM_ITEMS, depth) as i32;
+    let num_items = rng.gen_range(0, max_items + 1);
+    for _ in 0..num_items {
+        let (x, y) = floor[rng.gen_range(0, floor.len())];
+        if let Some((name, item_def)) = choose_spawn(&tables.items, depth, rng) {
+            let object = Object::new_item(x, y, name, item_def, get_new_object_id(objects));
+            objects.push(object);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::collections::HashSet;
+    use std::path::Path;
+    use tcod::colors::DARK_GREEN;
+
+    use crate::effects::EffectRegistry;
+    use crate::gamedata::load_game_data;
+
+    // Per generator, with the depth cycling so the mixed generator and vaults see every depth band
+    const SEEDS: u64 = 1000;
+    const POPULATED_SEEDS: u64 = 250;
+
+    fn game_data_dir() -> &'static Path {
+        Path::new(concat!(env!("CARGO_MANIFEST_DIR"), "/src/data"))
+    }
+
+    fn floor_tiles(map: &Map) -> usize {
+        map.iter().map(|column| column.iter().filter(|tile| !tile.blocked).count()).sum()
+    }
+
+    fn walled_map(width: i32, height: i32) -> Map {
+        vec![vec![Tile::wall(); height as usize]; width as usize]
+    }
+
+    /// Always starts the player inside a wall, so nothing it makes can be repaired.
+    struct BrokenGenerator {
+        calls: Cell<u32>,
+    }
+
+    impl MapGenerator for BrokenGenerator {
+        fn generate(&self, width: i32, height: i32, _depth: u32, _tables: &SpawnTables,
+                    _rng: &mut rand_pcg::Pcg32) -> Layout {
+            self.calls.set(self.calls.get() + 1);
+            let mut map = walled_map(width, height);
+            carve(&mut map, Rect::new(1, 1, 4, 4));
+            Layout { map, regions: vec![], start: (10, 5), exit: (2, 2), fixed_spawns: vec![] }
+        }
+    }
+
+    /// Checks everything `repair_layout` promises, naming `what` was being checked in any failure.
+    fn assert_playable(layout: &Layout, what: &str) {
+        let map = &layout.map;
+        let (start_x, start_y) = layout.start;
+        assert!(!map[start_x as usize][start_y as usize].blocked, "{}: start in a wall", what);
+        let reachable: HashSet<(i32, i32)> = flood_fill(map, start_x, start_y).into_iter().collect();
+        assert_eq!(reachable.len(), floor_tiles(map), "{}: floor cut off from the start", what);
+        assert!(reachable.contains(&layout.exit), "{}: exit unreachable", what);
+        for (tile, _) in &layout.fixed_spawns {
+            assert!(reachable.contains(tile), "{}: vault spawn unreachable", what);
+        }
+        for tile in layout.regions.iter().flatten() {
+            assert!(reachable.contains(tile), "{}: spawn point in a wall", what);
+        }
+        for x in 0..MAP_WIDTH {
+            for y in 0..MAP_HEIGHT {
+                if out_of_bounds(x, y) || x == MAP_WIDTH - 1 || y == MAP_HEIGHT - 1 {
+                    assert!(map[x as usize][y as usize].blocked, "{}: hole in the outer wall", what);
+                }
+            }
+        }
+    }
+
+    fn carve(map: &mut Map, room: Rect) {
+        for x in room.x1..room.x2 {
+            for y in room.y1..room.y2 {
+                map[x as usize][y as usize] = Tile::empty();
+            }
+        }
+    }
+
+    #[test]
+    fn every_generator_leaves_all_floor_reachable() {
+        let data = load_game_data(game_data_dir(), &EffectRegistry::default(), &GeneratorRegistry::default()).unwrap();
+        let generators = GeneratorRegistry::default();
+        for name in generators.names() {
+            for seed in 0..SEEDS {
+                let depth = 1 + (seed % 10) as u32;
+                let mut rng = rand_pcg::Pcg32::new(seed, 0);
+                let layout = generate_layout(generators.get(name).unwrap(), MAP_WIDTH, MAP_HEIGHT, depth, &data.spawns,
+                                             &mut rng);
+                assert_playable(&layout, &format!("{} seed {}", name, seed));
+            }
+        }
+    }
+
+    #[test]
+    fn a_generator_that_keeps_failing_is_replaced_by_plain_rooms() {
+        let data = load_game_data(game_data_dir(), &EffectRegistry::default(), &GeneratorRegistry::default()).unwrap();
+        let broken = BrokenGenerator { calls: Cell::new(0) };
+        let mut rng = rand_pcg::Pcg32::new(0, 0);
+        let layout = generate_layout(&broken, MAP_WIDTH, MAP_HEIGHT, 1, &data.spawns, &mut rng);
+        assert_eq!(broken.calls.get(), MAX_GENERATION_ATTEMPTS);
+        assert_playable(&layout, "fallback");
+        assert!(floor_tiles(&layout.map) > 1);
+
+        // Which is only safe as long as plain rooms really never fail
+        for seed in 0..SEEDS {
+            let mut rng = rand_pcg::Pcg32::new(seed, 0);
+            let mut layout = make_map(MAP_WIDTH, MAP_HEIGHT, 1, &SpawnTables::default(), &mut rng);
+            assert!(repair_layout(&mut layout), "plain rooms seed {}: beyond repair", seed);
+        }
+    }
+
+    #[test]
+    fn populated_servers_never_stack_monsters() {
+        let data = load_game_data(game_data_dir(), &EffectRegistry::default(), &GeneratorRegistry::default()).unwrap();
+        let generators = GeneratorRegistry::default();
+        for name in generators.names() {
+            for seed in 0..POPULATED_SEEDS {
+                let depth = 1 + (seed % 10) as u32;
+                let mut rng = rand_pcg::Pcg32::new(seed, 0);
+                let layout = generate_layout(generators.get(name).unwrap(), MAP_WIDTH, MAP_HEIGHT, depth, &data.spawns,
+                                             &mut rng);
+                let mut objects = vec![Object::new_player(0, 0, "player", '@', DARK_GREEN, 0, true, true, data.player)];
+                populate(&layout, &mut objects, &mut rng, depth, &data.spawns);
+
+                let reachable: HashSet<(i32, i32)> = flood_fill(&layout.map, layout.start.0, layout.start.1)
+                    .into_iter()
+                    .collect();
+                let mut blockers = HashSet::new();
+                for object in &objects {
+                    assert!(reachable.contains(&object.pos()), "{} seed {}: {} out of reach", name, seed, object.name);
+                    if object.blocks {
+                        assert!(blockers.insert(object.pos()), "{} seed {}: {} stacked at {:?}", name, seed, object.name,
+                                object.pos());
+                    }
+                }
+                assert_eq!(objects.iter().filter(|object| object.link == Some(ServerLink::Uplink)).count(), 1);
+            }
+        }
+    }
+
+    #[test]
+    fn repair_digs_out_a_cut_off_room() {
+        let mut map = walled_map(20, 10);
+        carve(&mut map, Rect::new(1, 1, 4, 4));
+        carve(&mut map, Rect::new(12, 3, 5, 5));
+        let mut layout = Layout { map, regions: vec![], start: (2, 2), exit: (14, 5), fixed_spawns: vec![] };
+
+        assert!(repair_layout(&mut layout));
+        assert_eq!(flood_fill(&layout.map, 2, 2).len(), floor_tiles(&layout.map));
+        // The shortest way across the gap between the rooms is 7 tiles long
+        assert_eq!(floor_tiles(&layout.map), 16 + 25 + 7);
+    }
+
+    #[test]
+    fn repair_fills_in_a_tiny_empty_pocket() {
+        let mut map = walled_map(20, 10);
+        carve(&mut map, Rect::new(1, 1, 4, 4));
+        carve(&mut map, Rect::new(12, 3, 1, 2));
+        let mut layout = Layout { map, regions: vec![vec![(12, 3), (2, 2)]], start: (2, 2), exit: (3, 3),
+                                  fixed_spawns: vec![] };
+
+        assert!(repair_layout(&mut layout));
+        assert!(layout.map[12][3].blocked && layout.map[12][4].blocked);
+        assert_eq!(layout.regions, vec![vec![(2, 2)]]);
+    }
+
+    #[test]
+    fn repair_keeps_a_tiny_pocket_holding_the_exit() {
+        let mut map = walled_map(20, 10);
+        carve(&mut map, Rect::new(1, 1, 4, 4));
+        carve(&mut map, Rect::new(12, 3, 1, 1));
+        let mut layout = Layout { map, regions: vec![], start: (2, 2), exit: (12, 3), fixed_spawns: vec![] };
+
+        assert!(repair_layout(&mut layout));
+        assert!(flood_fill(&layout.map, 2, 2).contains(&(12, 3)));
+    }
+
+    #[test]
+    fn repair_rejects_a_start_inside_a_wall() {
+        let mut map = walled_map(20, 10);
+        carve(&mut map, Rect::new(1, 1, 4, 4));
+        let mut layout = Layout { map, regions: vec![], start: (10, 5), exit: (2, 2), fixed_spawns: vec![] };
+
+        assert!(!repair_layout(&mut layout));
+    }
+}
@@ -0,0 +1,418 @@
+use tcod::colors::*;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::ai::{Ai, Behavior};
+use crate::game::{distance, message, monster_death, player_death, Message};
+use crate::scheduler::{Actor, NORMAL_SPEED};
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Object {
+    pub x: i32,
+    pub y: i32,
+    pub name: String,
+    pub character: char,
+    pub color: Color,
+    pub id: i32,
+    pub blocks: bool,
+    pub alive: bool,
+    pub fighter: Option<Fighter>,
+    pub ai: Option<Ai>,
+    pub actor: Option<Actor>,
+    pub item: Option<Item>,
+    pub item_type: Option<ItemType>,
+    pub equipment: Option<Equipment>,
+    pub link: Option<ServerLink>,
+    pub replication: Option<Replication>,
+    // How many identical files this object stands for
+    pub count: u32,
+}
+
+impl Object {
+    pub fn new(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool) -> Self {
+        Object { x, y, name: name.into(), character: ch, color, id, blocks, alive, fighter: None, ai: None, actor: None, item: None, item_type: None, equipment: None, link: None, replication: None, count: 1 }
+    }
+
+    pub fn new_player(x: i32, y: i32, name: &str, ch: char, color: Color, id: i32, blocks: bool, alive: bool,
+                      player_fighter: Fighter) -> Self {
+        Object {
+            x,
+            y,
+            name: name.into(),
+            character: ch,
+            color,
+            id,
+            blocks,
+            alive,
+            fighter: Some(player_fighter),
+            ai: None,
+            actor: Some(Actor::ready(NORMAL_SPEED)),
+            item: None,
+            item_type: None,
+            equipment: None,
+            link: None,
+            replication: None,
+            count: 1,
+        }
+    }
+
+    pub fn new_monster(x: i32, y: i32, name: &str, monster: &MonsterDef, id: i32) -> Self {
+        Object {
+            x,
+            y,
+            name: name.into(),
+            character: monster.glyph,
+            color: monster.color,
+            id,
+            blocks: true,
+            alive: true,
+            fighter: Some(monster.stats),
+            ai: Some(Ai::new(monster.ai)),
+            actor: Some(Actor::new(monster.speed)),
+            item: None,
+            item_type: None,
+            equipment: None,
+            link: None,
+            replication: monster.replicate.map(|replication| Replication { countdown: replication.every, ..replication }),
+            count: 1,
+        }
+    }
+
+    pub fn new_item(x: i32, y: i32, name: &str, item: &ItemDef, id: i32) -> Self {
+        Object {
+            x,
+            y,
+            name: name.into(),
+            character: item.glyph,
+            color: item.color,
+            id,
+            blocks: false,
+            alive: false,
+            fighter: None,
+            ai: None,
+            actor: None,
+            item: Some(item.item.clone()),
+            item_type: Some(item.item_type),
+            equipment: item.equipment,
+            link: None,
+            replication: None,
+            count: 1,
+        }
+    }
+
+    pub fn new_link(x: i32, y: i32, link: ServerLink, id: i32) -> Self {
+        let (name, ch) = match link {
+            ServerLink::Uplink => ("uplink", '>'),
+            ServerLink::Backlink => ("backlink", '<'),
+        };
+        let mut object = Object::new(x, y, name, ch, WHITE, id, false, false);
+        object.link = Some(link);
+        object
+    }
+
+    /// The name, with the size of the stack when there is more than one.
+    pub fn display_name(&self) -> String {
+        if self.count > 1 {
+            format!("{} x{}", self.name, self.count)
+        } else {
+            self.name.clone()
+        }
+    }
+
+    /// Whether `other` is the same file and can share a single inventory slot with this one.
+    /// Apps never stack, since each keeps its own install state.
+    pub fn stacks_with(&self, other: &Object) -> bool {
+        self.item.is_some()
+            && self.equipment.is_none()
+            && other.equipment.is_none()
+            && self.name == other.name
+            && self.item == other.item
+            && self.item_type == other.item_type
+    }
+
+    pub fn pos(&self) -> (i32, i32) {
+        (self.x, self.y)
+    }
+
+    pub fn set_pos(&mut self, x: i32, y: i32) {
+        self.x = x;
+        self.y = y;
+    }
+
+    pub fn distance_to(&self, other: &Object) -> f32 {
+        let dx = other.x - self.x;
+        let dy = other.y - self.y;
+        distance(dx, dy)
+    }
+
+    pub fn take_damage(&mut self, damage: i32, messages: &mut Vec<Message>) {
+        if let Some(fighter) = self.fighter.as_mut() {
+            if damage > 0 {
+                fighter.hp -= damage;
+            }
+        }
+
+        if let Some(fighter) = self.fighter {
+            if fighter.hp <= 0 {
+                self.alive = false;
+                fighter.on_death.callback(self, messages);
+            }
+        }
+    }
+
+    /// Power with the bonuses of every App in `apps` added. Only the player installs Apps,
+    /// so everyone else passes an empty slice.
+    pub fn power(&self, apps: &[Equipment]) -> i32 {
+        self.fighter.map_or(0, |f| f.power) + apps.iter().map(|app| app.power_bonus).sum::<i32>()
+    }
+
+    pub fn defense(&self, apps: &[Equipment]) -> i32 {
+        self.fighter.map_or(0, |f| f.defense) + apps.iter().map(|app| app.defense_bonus).sum::<i32>()
+    }
+
+    pub fn max_hp(&self, apps: &[Equipment]) -> i32 {
+        self.fighter.map_or(0, |f| f.max_hp) + apps.iter().map(|app| app.max_hp_bonus).sum::<i32>()
+    }
+
+    pub fn attack(&mut self, target: &mut Object, own_apps: &[Equipment], target_apps: &[Equipment],
+                  messages: &mut Vec<Message>) {
+        let damage = self.power(own_apps) - target.defense(target_apps);
+        if damage > 0 {
+            message(messages,
+                    format!("{} attacks {} for {} damage", self.name, target.name, damage),
+                    WHITE);
+            target.take_damage(damage, messages);
+        } else {
+            message(messages,
+                    format!("{} attacks {}, but it has no effect!", self.name, target.name),
+                    WHITE);
+        }
+    }
+
+    pub fn heal(&mut self, amount: i32, apps: &[Equipment]) {
+        let max_hp = self.max_hp(apps);
+        if let Some(ref mut fighter) = self.fighter {
+            fighter.hp += amount;
+            if fighter.hp > max_hp {
+                fighter.hp = max_hp;
+            }
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Fighter {
+    pub max_hp: i32,
+    pub hp: i32,
+    pub defense: i32,
+    pub power: i32,
+    pub on_death: DeathCallback
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum DeathCallback {
+    Player,
+    Monster,
+}
+
+impl DeathCallback {
+    fn callback(self, object: &mut Object, messages: &mut Vec<Message>) {
+        use DeathCallback::*;
+        let callback: fn(&mut Object, &mut Vec<Message>) = match self {
+            Player => player_death,
+            Monster => monster_death,
+        };
+        callback(object, messages);
+    }
+}
+
+/// Anything the map generators can roll for when filling a room.
+pub trait Spawnable {
+    fn spawn_weight(&self) -> u32;
+    fn min_depth(&self) -> u32;
+}
+
+/// Everything needed to spawn one kind of monster, as described in the game data.
+#[derive(Clone, Debug, Deserialize)]
+pub struct MonsterDef {
+    pub glyph: char,
+    pub color: Color,
+    pub ai: Behavior,
+    // Energy gained per tick; normal is 10
+    #[serde(default = "normal_speed")]
+    pub speed: i32,
+    pub spawn_weight: u32,
+    pub min_depth: u32,
+    pub stats: Fighter,
+    #[serde(default)]
+    pub replicate: Option<Replication>,
+}
+
+fn normal_speed() -> i32 {
+    NORMAL_SPEED
+}
+
+impl Spawnable for MonsterDef {
+    fn spawn_weight(&self) -> u32 {
+        self.spawn_weight
+    }
+
+    fn min_depth(&self) -> u32 {
+        self.min_depth
+    }
+}
+
+/// Everything needed to spawn one kind of file, as described in the game data.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ItemDef {
+    pub glyph: char,
+    pub color: Color,
+    pub item_type: ItemType,
+    #[serde(flatten)]
+    pub item: Item,
+    // Required for Apps, and only for Apps
+    #[serde(default)]
+    pub equipment: Option<Equipment>,
+    pub spawn_weight: u32,
+    pub min_depth: u32,
+}
+
+impl Spawnable for ItemDef {
+    fn spawn_weight(&self) -> u32 {
+        self.spawn_weight
+    }
+
+    fn min_depth(&self) -> u32 {
+        self.min_depth
+    }
+}
+
+/// A hand-authored room, stamped into the map in place of an ordinary one.
+#[derive(Clone, Debug, Deserialize)]
+pub struct VaultDef {
+    // One string per row: `#` is wall, `.` is floor, and any other character must be in the legend
+    pub template: Vec<String>,
+    #[serde(default)]
+    pub legend: BTreeMap<char, VaultSpawn>,
+    // Whether the generator may turn the template in quarter turns, or flip it left to right
+    #[serde(default)]
+    pub rotate: bool,
+    #[serde(default)]
+    pub mirror: bool,
+    pub spawn_weight: u32,
+    pub min_depth: u32,
+}
+
+impl Spawnable for VaultDef {
+    fn spawn_weight(&self) -> u32 {
+        self.spawn_weight
+    }
+
+    fn min_depth(&self) -> u32 {
+        self.min_depth
+    }
+}
+
+/// What always spawns on a vault's legend character, standing on floor.
+#[derive(Clone, Debug, Deserialize)]
+pub enum VaultSpawn {
+    Monster(String),
+    Item(String),
+}
+
+// Keyed by name; sorted so that seeded spawn rolls always walk them in the same order
+pub type MonsterTable = BTreeMap<String, MonsterDef>;
+pub type ItemTable = BTreeMap<String, ItemDef>;
+pub type VaultTable = BTreeMap<String, VaultDef>;
+
+/// The monsters, items and vaults a map generator may place.
+#[derive(Debug, Default)]
+pub struct SpawnTables {
+    pub monsters: MonsterTable,
+    pub items: ItemTable,
+    pub vaults: VaultTable,
+    // Percent chance that a room is swapped for a vault, where the generator supports them
+    pub vault_chance: u32,
+}
+
+/// A monster's habit of copying itself into a free tile next to it.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Replication {
+    // Turns between copies
+    pub every: u32,
+    // No more copies are made once this many of its kind are alive on the server
+    pub max_population: u32,
+    // Turns until the next copy, set from `every` whenever one is made
+    #[serde(default)]
+    pub countdown: u32,
+}
+
+/// Where a link object takes the player: one server deeper, or back the way they came.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ServerLink {
+    Uplink,
+    Backlink,
+}
+
+/// What a file does when executed: the name of an effect in the `EffectRegistry`,
+/// plus whatever numbers that effect reads.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Item {
+    #[serde(default)]
+    pub description: String,
+    // Scripts always have one; for an App it is an optional ability, usable while installed
+    #[serde(default)]
+    pub effect: String,
+    #[serde(default)]
+    pub targeting: Targeting,
+    #[serde(default)]
+    pub params: BTreeMap<String, i32>,
+}
+
+impl Item {
+    /// Reads a numeric parameter, treating a missing one as zero.
+    pub fn param(&self, name: &str) -> i32 {
+        self.params.get(name).cloned().unwrap_or(0)
+    }
+}
+
+/// What, if anything, a file has to be aimed at when it is executed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub enum Targeting {
+    #[default]
+    None,
+    Monster,
+    Tile,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum ItemType {
+    Script,
+    App,
+}
+
+impl fmt::Display for ItemType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ItemType::Script => write!(f, "Script"),
+            ItemType::App => write!(f, "App"),
+        }
+    }
+}
+
+/// The passive bonuses an App gives while it is installed.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct Equipment {
+    #[serde(default)]
+    pub installed: bool,
+    #[serde(default)]
+    pub power_bonus: i32,
+    #[serde(default)]
+    pub defense_bonus: i32,
+    #[serde(default)]
+    pub max_hp_bonus: i32,
+    // Turns until the App's ability can be used again
+    #[serde(default)]
+    pub recharge: i32,
+}
@@ -0,0 +1,360 @@
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap};
+
+use crate::map::Map;
+use crate::object::Object;
+
+// Every step costs this much, diagonals included, since monsters move in all eight directions
+const STEP_COST: u32 = 1;
+
+// Extra cost of a step onto a tile a blocking object is standing on. High enough that monsters walk
+// around each other when there is a short way round, low enough that a crowded corridor doesn't make
+// them give up and wander the long way through the whole maze.
+pub const BLOCKER_COST: u32 = 8;
+
+// Orthogonal steps first, so ties break the same way every time
+const NEIGHBOURS: [(i32, i32); 8] = [(0, -1), (1, 0), (0, 1), (-1, 0), (1, -1), (1, 1), (-1, 1), (-1, -1)];
+
+/// The cheapest route from `from` to `to`, as the tiles to step onto in order, ending on `to`.
+/// Walls can't be crossed, while blocking objects only make a tile more expensive, since they
+/// are likely to have moved by the time the route gets there. The goal itself is never treated
+/// as crowded, so a path can end on the player. Returns `None` if `to` can't be reached at all.
+pub fn find_path(map: &Map, objects: &[Object], from: (i32, i32), to: (i32, i32)) -> Option<Vec<(i32, i32)>> {
+    if !is_walkable(map, to) {
+        return None;
+    }
+    if from == to {
+        return Some(Vec::new());
+    }
+
+    let width = map.len();
+    let height = map.first().map_or(0, |column| column.len());
+    let index = |(x, y): (i32, i32)| x as usize * height + y as usize;
+
+    let mut crowded = vec![false; width * height];
+    for object in objects.iter().filter(|object| object.blocks && is_walkable(map, object.pos())) {
+        crowded[index(object.pos())] = true;
+    }
+
+    let mut cost = vec![u32::MAX; width * height];
+    let mut came_from: Vec<Option<(i32, i32)>> = vec![None; width * height];
+    // Ordered by estimated total cost, then by distance left, then first come first served
+    let mut open = BinaryHeap::new();
+    let mut pushed: u32 = 0;
+
+    cost[index(from)] = 0;
+    open.push(Reverse((heuristic(from, to), heuristic(from, to), pushed, from)));
+
+    while let Some(Reverse((_, _, _, pos))) = open.pop() {
+        if pos == to {
+            let mut path = vec![to];
+            let mut step = to;
+            while let Some(previous) = came_from[index(step)] {
+                if previous == from {
+                    break;
+                }
+                path.push(previous);
+                step = previous;
+            }
+            path.reverse();
+            return Some(path);
+        }
+
+        for (dx, dy) in NEIGHBOURS.iter() {
+            let next = (pos.0 + dx, pos.1 + dy);
+            if !is_walkable(map, next) {
+                continue;
+            }
+            let mut step_cost = STEP_COST;
+            if next != to && crowded[index(next)] {
+                step_cost += BLOCKER_COST;
+            }
+            let next_cost = cost[index(pos)] + step_cost;
+            if next_cost < cost[index(next)] {
+                cost[index(next)] = next_cost;
+                came_from[index(next)] = Some(pos);
+                pushed += 1;
+                let remaining = heuristic(next, to);
+                open.push(Reverse((next_cost + remaining, remaining, pushed, next)));
+            }
+        }
+    }
+    None
+}
+
+/// Moves in eight directions make this the number of steps left with nothing in the way.
+fn heuristic((x1, y1): (i32, i32), (x2, y2): (i32, i32)) -> u32 {
+    ((x1 - x2).abs().max((y1 - y2).abs()) as u32) * STEP_COST
+}
+
+fn is_walkable(map: &Map, (x, y): (i32, i32)) -> bool {
+    x >= 0 && y >= 0 && map.get(x as usize).and_then(|column| column.get(y as usize)).is_some_and(|tile| !tile.blocked)
+}
+
+/// A route a monster is following, with the crowded tiles it was planned around.
+#[derive(Debug)]
+struct CachedPath {
+    goal: (i32, i32),
+    // Where the monster was when it was last handed a step
+    at: (i32, i32),
+    // Still to walk, next step last
+    steps: Vec<(i32, i32)>,
+    crowded: Vec<(i32, i32)>,
+}
+
+/// Remembers each monster's route between turns, so A* only runs again when something changes.
+/// A route is dropped as soon as its goal moves, the monster is pushed off it, a tile along it
+/// turns to wall, or a blocking object steps onto or off it.
+#[derive(Debug, Default)]
+pub struct PathCache {
+    // Keyed by object id rather than index, since indices shift whenever an object is removed
+    paths: BTreeMap<i32, CachedPath>,
+}
+
+impl PathCache {
+    pub fn new() -> Self {
+        PathCache { paths: BTreeMap::new() }
+    }
+
+    /// The next tile the monster at `objects[index]` should step onto to reach `goal`.
+    pub fn next_step(&mut self, index: usize, goal: (i32, i32), map: &Map, objects: &[Object]) -> Option<(i32, i32)> {
+        let id = objects[index].id;
+        let from = objects[index].pos();
+        let cached = match self.paths.get_mut(&id) {
+            Some(path) if path.goal == goal => path.follow(from, map, objects),
+            _ => false,
+        };
+
+        if !cached {
+            let mut steps = find_path(map, objects, from, goal)?;
+            let crowded = steps
+                .iter()
+                .filter(|&&step| step != goal && has_blocker(objects, step))
+                .cloned()
+                .collect();
+            steps.reverse();
+            self.paths.insert(id, CachedPath { goal, at: from, steps, crowded });
+        }
+        self.paths.get(&id).and_then(|path| path.steps.last().cloned())
+    }
+
+    /// Drops every route, e.g. when the map they were planned on is swapped out.
+    pub fn clear(&mut self) {
+        self.paths.clear();
+    }
+}
+
+impl CachedPath {
+    /// Catches the route up with a monster now at `from`, and checks it is still worth following.
+    fn follow(&mut self, from: (i32, i32), map: &Map, objects: &[Object]) -> bool {
+        if from != self.at {
+            if self.steps.last() != Some(&from) {
+                return false;
+            }
+            self.steps.pop();
+            self.at = from;
+        }
+        if self.steps.is_empty() {
+            return false;
+        }
+
+        let goal = self.goal;
+        let crowded = &self.crowded;
+        let route_changed = self.steps.iter().filter(|&&step| step != goal).any(|&step| {
+            !is_walkable(map, step) || has_blocker(objects, step) != crowded.contains(&step)
+        });
+        !route_changed
+    }
+}
+
+fn has_blocker(objects: &[Object], pos: (i32, i32)) -> bool {
+    objects.iter().any(|object| object.blocks && object.pos() == pos)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tcod::colors::WHITE;
+
+    use crate::map::Tile;
+
+    /// Builds a map from rows of `#` walls and `.` floor; `@` and `g` mark the start and goal,
+    /// and `o` a blocking object standing on floor.
+    fn parse(rows: &[&str]) -> (Map, Vec<Object>, (i32, i32), (i32, i32)) {
+        let mut map = vec![vec![Tile::wall(); rows.len()]; rows[0].len()];
+        let mut objects = Vec::new();
+        let (mut start, mut goal) = ((0, 0), (0, 0));
+        for (y, row) in rows.iter().enumerate() {
+            for (x, tile) in row.chars().enumerate() {
+                let pos = (x as i32, y as i32);
+                if tile != '#' {
+                    map[x][y] = Tile::empty();
+                }
+                match tile {
+                    '@' => start = pos,
+                    'g' => goal = pos,
+                    'o' => objects.push(Object::new(pos.0, pos.1, "blocker", 'o', WHITE, objects.len() as i32, true, true)),
+                    _ => {}
+                }
+            }
+        }
+        (map, objects, start, goal)
+    }
+
+    fn assert_walkable(map: &Map, start: (i32, i32), path: &[(i32, i32)]) {
+        let mut previous = start;
+        for &step in path {
+            assert!(is_walkable(map, step), "{:?} is a wall", step);
+            assert!((step.0 - previous.0).abs() <= 1 && (step.1 - previous.1).abs() <= 1, "{:?} skips a tile", step);
+            previous = step;
+        }
+    }
+
+    #[test]
+    fn goes_around_a_wall_instead_of_into_it() {
+        let (map, objects, start, goal) = parse(&[
+            "#######",
+            "#..#..#",
+            "#@.#.g#",
+            "#..#..#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&map, &objects, start, goal).unwrap();
+        assert_walkable(&map, start, &path);
+        assert_eq!(path.last(), Some(&goal));
+        assert_eq!(path.len(), 4);
+    }
+
+    #[test]
+    fn solves_a_winding_maze() {
+        let (map, objects, start, goal) = parse(&[
+            "###########",
+            "#@#.....#g#",
+            "#.#.###.#.#",
+            "#.#.#...#.#",
+            "#.#.#.###.#",
+            "#...#.....#",
+            "###########",
+        ]);
+        let path = find_path(&map, &objects, start, goal).unwrap();
+        assert_walkable(&map, start, &path);
+        assert_eq!(path.last(), Some(&goal));
+        assert_eq!(path.len(), 20);
+    }
+
+    #[test]
+    fn gives_up_on_a_sealed_room() {
+        let (map, objects, start, goal) = parse(&[
+            "#######",
+            "#@.#..#",
+            "#..#.g#",
+            "#######",
+        ]);
+        assert_eq!(find_path(&map, &objects, start, goal), None);
+    }
+
+    #[test]
+    fn walks_around_a_blocker_when_there_is_room() {
+        let (map, objects, start, goal) = parse(&[
+            "#######",
+            "#.....#",
+            "#@.o.g#",
+            "#.....#",
+            "#######",
+        ]);
+        let path = find_path(&map, &objects, start, goal).unwrap();
+        assert_walkable(&map, start, &path);
+        assert!(!path.contains(&(3, 2)));
+        assert_eq!(path.len(), 4);
+    }
+
+    #[test]
+    fn queues_behind_a_blocker_with_no_way_round() {
+        let (map, objects, start, goal) = parse(&[
+            "#########",
+            "#@.o...g#",
+            "#########",
+        ]);
+        let path = find_path(&map, &objects, start, goal).unwrap();
+        assert_walkable(&map, start, &path);
+        assert_eq!(path.len(), 6);
+        assert!(path.contains(&(3, 1)));
+    }
+
+    #[test]
+    fn ends_on_a_goal_that_blocks() {
+        let (map, mut objects, start, goal) = parse(&[
+            "######",
+            "#@..g#",
+            "######",
+        ]);
+        objects.push(Object::new(goal.0, goal.1, "player", '@', WHITE, objects.len() as i32, true, true));
+        assert_eq!(find_path(&map, &objects, start, goal), Some(vec![(2, 1), (3, 1), goal]));
+    }
+
+    #[test]
+    fn cache_follows_its_route_until_it_changes() {
+        let (mut map, mut objects, start, goal) = parse(&[
+            "#########",
+            "#@......#",
+            "#.#####.#",
+            "#......g#",
+            "#########",
+        ]);
+        objects.insert(0, Object::new(start.0, start.1, "monster", 'm', WHITE, 0, true, true));
+        let mut cache = PathCache::new();
+
+        let first = cache.next_step(0, goal, &map, &objects).unwrap();
+        let planned = cache.paths[&0].steps.clone();
+        objects[0].set_pos(first.0, first.1);
+        let second = cache.next_step(0, goal, &map, &objects).unwrap();
+        assert_eq!(cache.paths[&0].steps[..], planned[..planned.len() - 1]);
+
+        // Walling off the route it was on sends it back the other way
+        map[second.0 as usize][second.1 as usize] = Tile::wall();
+        let detour = cache.next_step(0, goal, &map, &objects).unwrap();
+        assert_ne!(detour, second);
+        assert_walkable(&map, first, &[detour]);
+    }
+
+    #[test]
+    fn cache_replans_when_a_blocker_steps_onto_the_route() {
+        let (map, mut objects, start, goal) = parse(&[
+            "#######",
+            "#.....#",
+            "#@...g#",
+            "#.....#",
+            "#######",
+        ]);
+        objects.insert(0, Object::new(start.0, start.1, "monster", 'm', WHITE, 0, true, true));
+        let mut cache = PathCache::new();
+        assert_eq!(cache.next_step(0, goal, &map, &objects), Some((2, 2)));
+
+        objects.push(Object::new(2, 2, "blocker", 'o', WHITE, 1, true, true));
+        let step = cache.next_step(0, goal, &map, &objects).unwrap();
+        assert_ne!(step, (2, 2));
+        assert!(cache.paths[&0].crowded.is_empty());
+    }
+
+    #[test]
+    fn cache_keeps_a_route_when_the_monster_changes_index() {
+        let (map, mut objects, start, goal) = parse(&[
+            "#######",
+            "#@...g#",
+            "#######",
+        ]);
+        objects.push(Object::new(3, 1, "file", '!', WHITE, 3, false, false));
+        objects.push(Object::new(start.0, start.1, "monster", 'm', WHITE, 7, true, true));
+        let mut cache = PathCache::new();
+
+        let first = cache.next_step(1, goal, &map, &objects).unwrap();
+        let planned = cache.paths[&7].steps.clone();
+        objects[1].set_pos(first.0, first.1);
+        // Picking up the file moves the monster into its slot
+        objects.swap_remove(0);
+        assert_eq!(cache.next_step(0, goal, &map, &objects), Some((3, 1)));
+        assert_eq!(cache.paths.len(), 1);
+        assert_eq!(cache.paths[&7].steps[..], planned[..planned.len() - 1]);
+    }
+}
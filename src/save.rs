@@ -0,0 +1,59 @@
+use serde::{Deserialize, Serialize};
+use std::error::Error;
+use std::fs;
+use std::path::Path;
+use std::rc::Rc;
+
+use crate::game::GameState;
+use crate::gamedata::GameData;
+
+pub const SAVE_FILE: &str = "savegame.json";
+
+// Bump whenever a saved field changes shape so stale saves are refused instead of misread
+const SAVE_VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize)]
+struct SaveFile<G> {
+    version: u32,
+    game: G,
+}
+
+#[derive(Deserialize)]
+struct SaveHeader {
+    version: u32,
+}
+
+pub fn save_exists() -> bool {
+    Path::new(SAVE_FILE).exists()
+}
+
+pub fn save_game(game: &GameState) -> Result<(), Box<dyn Error>> {
+    let save = SaveFile { version: SAVE_VERSION, game };
+    let save_data = serde_json::to_string(&save)?;
+    fs::write(SAVE_FILE, save_data)?;
+    Ok(())
+}
+
+pub fn load_game(data: Rc<GameData>) -> Result<GameState, Box<dyn Error>> {
+    let save_data = fs::read_to_string(SAVE_FILE)?;
+
+    let header: SaveHeader = serde_json::from_str(&save_data)?;
+    if header.version != SAVE_VERSION {
+        return Err(format!("save file is version {}, expected version {}", header.version, SAVE_VERSION).into());
+    }
+
+    let save: SaveFile<GameState> = serde_json::from_str(&save_data)?;
+    let mut game = save.game;
+    if game.generators.get(&game.generator).is_none() {
+        return Err(format!("save file uses unknown map generator `{}`", game.generator).into());
+    }
+    game.data = data;
+    game.init_fov();
+    Ok(game)
+}
+
+pub fn delete_save() {
+    if save_exists() {
+        let _ = fs::remove_file(SAVE_FILE);
+    }
+}
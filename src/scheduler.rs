@@ -0,0 +1,260 @@
+use serde::{Deserialize, Serialize};
+use std::cmp;
+
+// Energy an actor needs banked before it can act
+pub const TURN_ENERGY: i32 = 100;
+// Energy gained per tick at normal speed, so a normal actor acts once every 10 ticks
+pub const NORMAL_SPEED: i32 = 10;
+pub const TICKS_PER_TURN: i32 = TURN_ENERGY / NORMAL_SPEED;
+
+// What each kind of action costs. Every cost must be positive, or the actor would never stop acting.
+pub const MOVE_COST: i32 = 100;
+pub const ATTACK_COST: i32 = 120;
+pub const EXEC_COST: i32 = 150;
+// Anything else, including standing still
+pub const ACTION_COST: i32 = 100;
+
+/// How fast an object acts, and how close it is to acting again.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Actor {
+    // Energy gained every tick
+    pub speed: i32,
+    pub energy: i32,
+    // A haste or slow that is wearing off
+    pub boost: Option<SpeedBoost>,
+}
+
+/// Scales an actor's speed by `percent` for the next `ticks` ticks.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct SpeedBoost {
+    pub percent: i32,
+    pub ticks: i32,
+}
+
+impl Actor {
+    /// An actor that has to wait a full turn before it acts.
+    pub fn new(speed: i32) -> Self {
+        Actor { speed, energy: 0, boost: None }
+    }
+
+    /// An actor that can act straight away.
+    pub fn ready(speed: i32) -> Self {
+        Actor { speed, energy: TURN_ENERGY, boost: None }
+    }
+
+    /// The energy gained per tick right now, with any haste or slow applied. Never less than 1,
+    /// so even the slowest actor gets a turn eventually.
+    pub fn current_speed(&self) -> i32 {
+        match self.boost {
+            Some(boost) => cmp::max(1, self.speed * boost.percent / 100),
+            None => cmp::max(1, self.speed),
+        }
+    }
+
+    pub fn is_ready(&self) -> bool {
+        self.energy >= TURN_ENERGY
+    }
+
+    pub fn spend(&mut self, cost: i32) {
+        self.energy -= cost;
+    }
+
+    /// Hastes (over 100) or slows (under 100) the actor for `turns` turns of normal-speed time.
+    /// A new boost replaces whatever one was already running.
+    pub fn boost(&mut self, percent: i32, turns: i32) {
+        self.boost = Some(SpeedBoost { percent, ticks: turns * TICKS_PER_TURN });
+    }
+
+    fn tick(&mut self) {
+        self.energy += self.current_speed();
+        if let Some(boost) = self.boost.as_mut() {
+            boost.ticks -= 1;
+            if boost.ticks <= 0 {
+                self.boost = None;
+            }
+        }
+    }
+}
+
+/// Whatever holds the actors, seen as numbered slots that may or may not be able to act.
+pub trait Timeline {
+    /// How many slots there are. It may grow while actors act, but never shrinks.
+    fn slot_count(&self) -> usize;
+
+    /// The actor in `slot`, if anything there can still act.
+    fn actor(&mut self, slot: usize) -> Option<&mut Actor>;
+
+    /// Lets the actor in `slot` take one action, returning what it cost.
+    fn act(&mut self, slot: usize) -> i32;
+}
+
+/// Runs time forward after the player has spent energy on an action, until they can act again.
+/// Everyone else with enough energy acts, those with the most going first and ties going to the
+/// lower slot. When no one else is ready and the player isn't either, a tick passes and every
+/// actor gains energy. An actor that gets faster than the player can take several actions in
+/// between the player's.
+pub fn run_until_ready<T: Timeline>(timeline: &mut T, player: usize) {
+    loop {
+        while let Some(slot) = next_ready(timeline, player) {
+            let cost = timeline.act(slot);
+            if let Some(actor) = timeline.actor(slot) {
+                actor.spend(cost);
+            }
+        }
+        if timeline.actor(player).is_none_or(|actor| actor.is_ready()) {
+            return;
+        }
+        for slot in 0..timeline.slot_count() {
+            if let Some(actor) = timeline.actor(slot) {
+                actor.tick();
+            }
+        }
+    }
+}
+
+fn next_ready<T: Timeline>(timeline: &mut T, player: usize) -> Option<usize> {
+    let mut next: Option<(usize, i32)> = None;
+    for slot in (0..timeline.slot_count()).filter(|&slot| slot != player) {
+        if let Some(actor) = timeline.actor(slot) {
+            if actor.is_ready() && next.is_none_or(|(_, energy)| actor.energy > energy) {
+                next = Some((slot, actor.energy));
+            }
+        }
+    }
+    next.map(|(slot, _)| slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PLAYER: usize = 0;
+
+    /// Actors that record each action they take and always pay the same cost for it.
+    struct Log {
+        actors: Vec<Option<Actor>>,
+        costs: Vec<i32>,
+        acted: Vec<usize>,
+    }
+
+    impl Log {
+        fn new(speeds: &[i32]) -> Self {
+            let mut actors: Vec<Option<Actor>> = speeds.iter().map(|&speed| Some(Actor::new(speed))).collect();
+            actors[PLAYER] = Some(Actor::ready(speeds[PLAYER]));
+            Log { actors, costs: vec![ACTION_COST; speeds.len()], acted: Vec::new() }
+        }
+
+        /// The player takes `turns` actions costing `cost`, and everyone else catches up after each.
+        fn play(&mut self, turns: usize, cost: i32) {
+            for _ in 0..turns {
+                self.acted.push(PLAYER);
+                self.actors[PLAYER].as_mut().unwrap().spend(cost);
+                run_until_ready(self, PLAYER);
+            }
+        }
+
+        fn actions(&self, slot: usize) -> usize {
+            self.acted.iter().filter(|&&acted| acted == slot).count()
+        }
+    }
+
+    impl Timeline for Log {
+        fn slot_count(&self) -> usize {
+            self.actors.len()
+        }
+
+        fn actor(&mut self, slot: usize) -> Option<&mut Actor> {
+            self.actors[slot].as_mut()
+        }
+
+        fn act(&mut self, slot: usize) -> i32 {
+            self.acted.push(slot);
+            self.costs[slot]
+        }
+    }
+
+    #[test]
+    fn equal_speeds_take_turns_one_for_one() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED, NORMAL_SPEED]);
+        log.play(3, MOVE_COST);
+        assert_eq!(log.acted, vec![0, 1, 2, 0, 1, 2, 0, 1, 2]);
+    }
+
+    #[test]
+    fn a_double_speed_actor_acts_twice_a_turn() {
+        let mut log = Log::new(&[NORMAL_SPEED, 2 * NORMAL_SPEED]);
+        log.play(10, MOVE_COST);
+        assert_eq!(log.actions(1), 20);
+    }
+
+    #[test]
+    fn a_half_speed_actor_acts_every_other_turn() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED / 2]);
+        log.play(10, MOVE_COST);
+        assert_eq!(log.actions(1), 5);
+    }
+
+    #[test]
+    fn dearer_actions_give_others_more_time() {
+        let mut cheap = Log::new(&[NORMAL_SPEED, NORMAL_SPEED]);
+        cheap.play(10, MOVE_COST);
+        let mut dear = Log::new(&[NORMAL_SPEED, NORMAL_SPEED]);
+        dear.play(10, EXEC_COST);
+        assert_eq!(cheap.actions(1), 10);
+        assert_eq!(dear.actions(1), 15);
+    }
+
+    #[test]
+    fn monsters_pay_for_their_own_actions() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED, NORMAL_SPEED]);
+        log.costs[2] = 2 * ACTION_COST;
+        log.play(10, MOVE_COST);
+        assert_eq!(log.actions(1), 10);
+        assert_eq!(log.actions(2), 5);
+    }
+
+    #[test]
+    fn the_most_energy_goes_first() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED, NORMAL_SPEED]);
+        log.actors[2].as_mut().unwrap().energy = 5;
+        log.play(1, MOVE_COST);
+        assert_eq!(log.acted, vec![0, 2, 1]);
+    }
+
+    #[test]
+    fn a_haste_wears_off() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED]);
+        log.actors[1].as_mut().unwrap().boost(200, 5);
+        log.play(10, MOVE_COST);
+        // Twice as fast for the first 5 turns, then back to normal
+        assert_eq!(log.actions(1), 15);
+        assert_eq!(log.actors[1].unwrap().boost, None);
+    }
+
+    #[test]
+    fn a_hasted_player_gets_extra_turns() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED]);
+        log.actors[PLAYER].as_mut().unwrap().boost(200, 100);
+        log.play(20, MOVE_COST);
+        assert_eq!(log.actions(1), 10);
+    }
+
+    #[test]
+    fn a_slow_never_stops_an_actor_outright() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED]);
+        log.actors[1].as_mut().unwrap().boost(0, 100);
+        log.play(20, MOVE_COST);
+        assert_eq!(log.actions(1), 2);
+    }
+
+    #[test]
+    fn empty_slots_and_newcomers_are_handled() {
+        let mut log = Log::new(&[NORMAL_SPEED, NORMAL_SPEED, NORMAL_SPEED]);
+        log.actors[1] = None;
+        log.play(2, MOVE_COST);
+        log.actors.push(Some(Actor::new(NORMAL_SPEED)));
+        log.costs.push(ACTION_COST);
+        log.play(1, MOVE_COST);
+        assert_eq!(log.acted, vec![0, 2, 0, 2, 0, 2, 3]);
+    }
+}
@@ -0,0 +1,841 @@
+use tcod::colors::*;
+use std::cmp;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::Path;
+
+use crate::effects::UseResult;
+use crate::map::out_of_bounds;
+use crate::object::{ItemType, Object, Targeting};
+use crate::scheduler::{ACTION_COST, EXEC_COST};
+use crate::game::{drop_item, get_inventory_item_by_name, installed_apps, message, use_item, GameState, PlayerAction,
+                  APP_SLOTS, PLAYER};
+
+// Where `source` looks for a macro that is not found relative to the working directory
+const MACRO_DIR: &str = "macros";
+// Plenty for any real macro, while still stopping an alias or macro that ends up invoking itself
+const MAX_CHAINED_COMMANDS: usize = 200;
+
+/// Runs one command against the game. Returning an error reports it without taking a turn.
+pub type CommandFn = fn(&mut GameState, &[String]) -> Result<PlayerAction, String>;
+
+#[derive(Clone, Copy)]
+pub struct CommandDef {
+    pub usage: &'static str,
+    pub summary: &'static str,
+    pub run: CommandFn,
+}
+
+/// Every command the backtick prompt understands, keyed by name.
+pub struct CommandRegistry {
+    // Sorted so `help` lists commands alphabetically
+    commands: BTreeMap<String, CommandDef>,
+}
+
+impl CommandRegistry {
+    pub fn new() -> Self {
+        CommandRegistry { commands: BTreeMap::new() }
+    }
+
+    pub fn register(&mut self, name: &str, usage: &'static str, summary: &'static str, run: CommandFn) {
+        self.commands.insert(name.to_string(), CommandDef { usage, summary, run });
+    }
+
+    pub fn get(&self, name: &str) -> Option<CommandDef> {
+        self.commands.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.commands.keys()
+    }
+}
+
+impl Default for CommandRegistry {
+    /// A registry holding every command the game ships with.
+    fn default() -> Self {
+        let mut registry = CommandRegistry::new();
+        registry.register("help", "help [command]", "List commands, or explain one", help);
+        registry.register("ls", "ls", "List files in the local filesystem", ls);
+        registry.register("cat", "cat <file>", "Print a file's contents", cat);
+        registry.register("info", "info <file>", "Show what a file is and does", info);
+        registry.register("exec", "exec <file> [target]", "Execute a script or App ability", exec);
+        registry.register("look", "look <target>", "Describe what is at a monster, direction or x,y", look);
+        registry.register("install", "install <app>", "Install an App into a free slot", install);
+        registry.register("uninstall", "uninstall <app>", "Uninstall an App", uninstall);
+        registry.register("drop", "drop <file> [count]", "Drop files onto the floor", drop);
+        registry.register("alias", "alias [name [command]]", "List, show or define aliases", alias);
+        registry.register("unalias", "unalias <name>", "Remove an alias", unalias);
+        registry.register("source", "source <macro>", "Run the commands in a macro file", source);
+        registry
+    }
+}
+
+/// Splits a command line into words. Single or double quotes group words containing spaces,
+/// and a backslash escapes the character after it.
+pub fn tokenize(line: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut in_token = false;
+    let mut quote: Option<char> = None;
+    let mut chars = line.chars();
+
+    while let Some(ch) = chars.next() {
+        match (ch, quote) {
+            ('\\', _) => {
+                match chars.next() {
+                    Some(escaped) => current.push(escaped),
+                    None => return Err("Nothing to escape after `\\`".to_string()),
+                }
+                in_token = true;
+            }
+            (_, Some(q)) if ch == q => quote = None,
+            (_, Some(_)) => current.push(ch),
+            ('"', None) | ('\'', None) => {
+                quote = Some(ch);
+                in_token = true;
+            }
+            (_, None) if ch.is_whitespace() => {
+                if in_token {
+                    tokens.push(current.split_off(0));
+                    in_token = false;
+                }
+            }
+            (_, None) => {
+                current.push(ch);
+                in_token = true;
+            }
+        }
+    }
+
+    if let Some(q) = quote {
+        return Err(format!("Unterminated {} quote", q));
+    }
+    if in_token {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+/// Splits a command line at every `;` that is not quoted or escaped.
+pub fn split_commands(line: &str) -> Vec<String> {
+    let mut commands = Vec::new();
+    let mut current = String::new();
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for ch in line.chars() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if quote == Some(ch) {
+            quote = None;
+        } else if quote.is_none() && (ch == '"' || ch == '\'') {
+            quote = Some(ch);
+        } else if quote.is_none() && ch == ';' {
+            commands.push(current.split_off(0));
+            continue;
+        }
+        current.push(ch);
+    }
+    commands.push(current);
+    commands.into_iter().filter(|command| !command.trim().is_empty()).collect()
+}
+
+/// Finds the byte offset where the last word of `line` starts, treating quoted spaces as part of the word.
+fn last_word_start(line: &str) -> usize {
+    let mut start = 0;
+    let mut quote: Option<char> = None;
+    let mut escaped = false;
+    for (i, ch) in line.char_indices() {
+        if escaped {
+            escaped = false;
+        } else if ch == '\\' {
+            escaped = true;
+        } else if quote == Some(ch) {
+            quote = None;
+        } else if quote.is_none() && (ch == '"' || ch == '\'') {
+            quote = Some(ch);
+        } else if quote.is_none() && ch.is_whitespace() {
+            start = i + ch.len_utf8();
+        }
+    }
+    start
+}
+
+/// Quotes a word if the tokenizer would otherwise split or mangle it.
+pub fn quote_word(word: &str) -> String {
+    if word.is_empty() || word.chars().any(|ch| ch.is_whitespace() || ch == '"' || ch == '\'' || ch == '\\') {
+        format!("\"{}\"", word.replace('\\', "\\\\").replace('"', "\\\""))
+    } else {
+        word.to_string()
+    }
+}
+
+/// What Tab could put in place of the last word of `line`: a command name for the first word,
+/// otherwise a file in the local filesystem. Returns the byte offset where that word starts,
+/// and the sorted, already-quoted candidates.
+pub fn completions(game: &GameState, line: &str) -> (usize, Vec<String>) {
+    let start = last_word_start(line);
+    // An unfinished quote is still a prefix worth completing
+    let partial = tokenize(&line[start..])
+        .or_else(|_| tokenize(&format!("{}\"", &line[start..])))
+        .or_else(|_| tokenize(&format!("{}'", &line[start..])))
+        .ok()
+        .and_then(|tokens| tokens.into_iter().next())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    let names: Vec<&String> = if line[..start].trim().is_empty() {
+        game.commands.names().chain(game.aliases.keys()).collect()
+    } else {
+        game.inventory.iter().map(|file| &file.name).collect()
+    };
+    let mut candidates: Vec<String> = names
+        .into_iter()
+        .filter(|name| name.to_ascii_lowercase().starts_with(&partial))
+        .map(|name| quote_word(name))
+        .collect();
+    candidates.sort();
+    candidates.dedup();
+    (start, candidates)
+}
+
+/// Runs everything on a command line: `;`-chained commands, aliases and sourced macros.
+///
+/// Each command takes its turn exactly as it would on its own, with the monsters acting in
+/// between. A failed command is reported and the rest carry on, but everything still queued
+/// is dropped if the player dies or a monster that was not in view when the line was entered
+/// comes into view.
+pub fn run_line(game: &mut GameState, line: &str) -> PlayerAction {
+    let watched = visible_monsters(game);
+    game.pending.clear();
+    queue_commands(game, split_commands(line));
+
+    let mut result = PlayerAction::NoTurn;
+    let mut executed = 0;
+    while let Some(command) = game.pending.pop_front() {
+        executed += 1;
+        if executed > MAX_CHAINED_COMMANDS {
+            message(&mut game.messages, "Too many chained commands. Stopping.", YELLOW);
+            game.pending.clear();
+            break;
+        }
+
+        if let PlayerAction::TookTurn(cost) = run_command(game, &command) {
+            result = PlayerAction::TookTurn(cost);
+            game.end_turn(cost);
+        }
+
+        if game.pending.is_empty() {
+            break;
+        }
+        if !game.objects[PLAYER].alive {
+            game.pending.clear();
+            break;
+        }
+        let intruder = visible_monsters(game)
+            .into_iter()
+            .find(|id| !watched.contains(id))
+            .and_then(|id| game.objects.iter().find(|object| object.id == id))
+            .map(|monster| monster.name.clone());
+        if let Some(name) = intruder {
+            message(&mut game.messages, format!("{} came into view. Remaining commands aborted.", name), YELLOW);
+            game.pending.clear();
+        }
+    }
+    result
+}
+
+/// Ids of the living monsters the player can currently see.
+fn visible_monsters(game: &GameState) -> Vec<i32> {
+    game.objects
+        .iter()
+        .filter(|object| object.ai.is_some() && object.alive && game.fov_map.is_in_fov(object.x, object.y))
+        .map(|object| object.id)
+        .collect()
+}
+
+/// Puts `commands` at the front of the queue, so they run before anything already waiting.
+fn queue_commands(game: &mut GameState, commands: Vec<String>) {
+    for command in commands.into_iter().rev() {
+        game.pending.push_front(command);
+    }
+}
+
+/// Parses and runs a single command, reporting any problem in the message log.
+pub fn run_command(game: &mut GameState, line: &str) -> PlayerAction {
+    let tokens = match tokenize(line) {
+        Ok(tokens) => tokens,
+        Err(e) => {
+            message(&mut game.messages, e, YELLOW);
+            return PlayerAction::NoTurn;
+        }
+    };
+    let (name, args) = match tokens.split_first() {
+        Some((name, args)) => (name.to_ascii_lowercase(), args),
+        None => return PlayerAction::NoTurn,
+    };
+
+    match game.commands.get(&name) {
+        Some(command) => match (command.run)(game, args) {
+            Ok(action) => action,
+            Err(e) => {
+                message(&mut game.messages, e, YELLOW);
+                PlayerAction::NoTurn
+            }
+        },
+        None => match game.aliases.get(&name) {
+            Some(expansion) => {
+                let mut expanded = expansion.clone();
+                for arg in args {
+                    expanded.push(' ');
+                    expanded.push_str(&quote_word(arg));
+                }
+                queue_commands(game, split_commands(&expanded));
+                PlayerAction::NoTurn
+            }
+            None => {
+                message(&mut game.messages, format!("Unknown command `{}`. Try `help`.", name), YELLOW);
+                PlayerAction::NoTurn
+            }
+        },
+    }
+}
+
+fn usage(command: &CommandDef) -> String {
+    format!("Usage: {}", command.usage)
+}
+
+fn help(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    match args {
+        [] => {
+            let lines: Vec<String> = game.commands.commands
+                .values()
+                .map(|command| format!("{:<24}{}", command.usage, command.summary))
+                .collect();
+            for line in lines {
+                message(&mut game.messages, line, WHITE);
+            }
+        }
+        [name] => {
+            let command = game.commands.get(&name.to_ascii_lowercase())
+                .ok_or_else(|| format!("No help for unknown command `{}`", name))?;
+            message(&mut game.messages, usage(&command), WHITE);
+            message(&mut game.messages, command.summary, WHITE);
+        }
+        _ => return Err(usage(&game.commands.get("help").unwrap())),
+    }
+    Ok(PlayerAction::NoTurn)
+}
+
+fn ls(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    if !args.is_empty() {
+        return Err(usage(&game.commands.get("ls").unwrap()));
+    }
+    if game.inventory.is_empty() {
+        message(&mut game.messages, "Local filesystem is empty.", WHITE);
+    }
+    let lines: Vec<String> = game.inventory
+        .iter()
+        .map(|file| match (file.item_type, file.equipment) {
+            (Some(item_type), Some(app)) if app.installed => format!("{:<20}{} (installed)", file.display_name(), item_type),
+            (Some(item_type), _) => format!("{:<20}{}", file.display_name(), item_type),
+            (None, _) => file.display_name(),
+        })
+        .collect();
+    for line in lines {
+        message(&mut game.messages, line, WHITE);
+    }
+    Ok(PlayerAction::NoTurn)
+}
+
+/// Looks up the single file argument that `cat` and `info` expect.
+fn file_arg(game: &GameState, command: &str, args: &[String]) -> Result<usize, String> {
+    match args {
+        [name] => get_inventory_item_by_name(&game.inventory, name)
+            .ok_or_else(|| format!("{}: `{}`: File Not Found", command, name)),
+        _ => Err(usage(&game.commands.get(command).unwrap())),
+    }
+}
+
+fn cat(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let id = file_arg(game, "cat", args)?;
+    let file = &game.inventory[id];
+    let text = match &file.item {
+        Some(item) if !item.description.is_empty() => item.description.clone(),
+        _ => format!("`{}` is unreadable.", file.name),
+    };
+    message(&mut game.messages, text, WHITE);
+    Ok(PlayerAction::NoTurn)
+}
+
+fn info(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let id = file_arg(game, "info", args)?;
+    for line in describe_file(&game.inventory[id]) {
+        message(&mut game.messages, line, WHITE);
+    }
+    Ok(PlayerAction::NoTurn)
+}
+
+/// What `info` reports about a file, one line at a time.
+pub fn describe_file(file: &Object) -> Vec<String> {
+    let mut lines = vec![format!("Name: {}", file.name)];
+    if let Some(item_type) = file.item_type {
+        lines.push(format!("Type: {}", item_type));
+    }
+    if file.count > 1 {
+        lines.push(format!("Copies: {}", file.count));
+    }
+    if let Some(app) = file.equipment {
+        lines.push(format!("Installed: {}", if app.installed { "yes" } else { "no" }));
+        for (stat, bonus) in &[("Power", app.power_bonus), ("Defense", app.defense_bonus), ("Max HP", app.max_hp_bonus)] {
+            if *bonus != 0 {
+                lines.push(format!("  {} {:+}", stat, bonus));
+            }
+        }
+    }
+    if let Some(item) = file.item.as_ref().filter(|item| !item.effect.is_empty()) {
+        lines.push(format!("Effect: {}", item.effect));
+        for (param, value) in &item.params {
+            lines.push(format!("  {}: {}", param, value));
+        }
+    }
+    lines
+}
+
+fn exec(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let (name, target_words) = args.split_first()
+        .ok_or_else(|| usage(&game.commands.get("exec").unwrap()))?;
+    let id = get_inventory_item_by_name(&game.inventory, name)
+        .ok_or_else(|| format!("exec: `{}`: File Not Found", name))?;
+
+    if let Some(app) = game.inventory[id].equipment {
+        let has_ability = game.inventory[id].item.as_ref().is_some_and(|item| !item.effect.is_empty());
+        if !has_ability {
+            return Err(format!("exec: `{}` has no ability to run", name));
+        }
+        if !app.installed {
+            return Err(format!("exec: `{}` must be installed first", name));
+        }
+        if app.recharge > 0 {
+            return Err(format!("exec: `{}` is recharging ({} turns)", name, app.recharge));
+        }
+    }
+
+    let targeting = game.inventory[id].item.as_ref().map_or(Targeting::None, |item| item.targeting);
+    let target = match (targeting, target_words) {
+        (Targeting::None, []) => None,
+        (Targeting::None, _) => return Err(format!("exec: `{}` does not take a target", name)),
+        (_, []) => return Err(format!("exec: `{}` needs a target: a monster, a direction or x,y", name)),
+        (_, words) => Some(parse_target(game, words)?),
+    };
+
+    let living_before = living_monsters(game);
+    match use_item(id, target, game) {
+        UseResult::Used => {
+            game.stats.scripts_executed += 1;
+            game.stats.kills += (living_before - living_monsters(game)) as u32;
+            Ok(PlayerAction::TookTurn(EXEC_COST))
+        }
+        // A misfire is reported like any other mistake, and costs nothing
+        UseResult::Cancelled => Ok(PlayerAction::NoTurn),
+    }
+}
+
+fn look(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    if args.is_empty() {
+        return Err(usage(&game.commands.get("look").unwrap()));
+    }
+    let target = parse_target(game, args)?;
+    let description = describe_tile(game, target);
+    message(&mut game.messages, description, WHITE);
+    Ok(PlayerAction::NoTurn)
+}
+
+/// What the player can see at `pos`, with whether each monster there is asleep, unaware, alert,
+/// fleeing or confused.
+pub fn describe_tile(game: &GameState, (x, y): (i32, i32)) -> String {
+    let seen: Vec<String> = game.objects
+        .iter()
+        .filter(|object| object.pos() == (x, y) && game.fov_map.is_in_fov(x, y))
+        .map(|object| match &object.ai {
+            Some(ai) => format!("{} ({})", object.name, ai.status()),
+            None => object.name.clone(),
+        })
+        .collect();
+    format!("({}, {}): {}", x, y, seen.join(", "))
+}
+
+fn living_monsters(game: &GameState) -> usize {
+    game.objects.iter().filter(|object| object.ai.is_some() && object.alive).count()
+}
+
+/// Turns the words after a file name into the tile it is aimed at. Accepts a direction
+/// (`n`, `se`, `west`...), coordinates (`x,y` or `x y`), or the name of a visible monster.
+pub fn parse_target(game: &GameState, words: &[String]) -> Result<(i32, i32), String> {
+    if let [word] = words {
+        if let Some(direction) = parse_direction(word) {
+            return Ok(first_in_line(game, direction));
+        }
+        let coords: Vec<&str> = word.split(',').collect();
+        if let [x, y] = coords.as_slice() {
+            return parse_coords(x, y);
+        }
+    }
+    if let [x, y] = words {
+        if x.parse::<i32>().is_ok() && y.parse::<i32>().is_ok() {
+            return parse_coords(x, y);
+        }
+    }
+
+    let name = words.join(" ").to_ascii_lowercase();
+    let player = &game.objects[PLAYER];
+    game.objects
+        .iter()
+        .filter(|object| object.ai.is_some() && object.alive && object.name.to_ascii_lowercase() == name)
+        .filter(|object| game.fov_map.is_in_fov(object.x, object.y))
+        .min_by(|a, b| a.distance_to(player).partial_cmp(&b.distance_to(player)).unwrap())
+        .map(|object| object.pos())
+        .ok_or_else(|| format!("No `{}` in sight", words.join(" ")))
+}
+
+fn parse_coords(x: &str, y: &str) -> Result<(i32, i32), String> {
+    match (x.trim().parse::<i32>(), y.trim().parse::<i32>()) {
+        (Ok(x), Ok(y)) if !out_of_bounds(x, y) => Ok((x, y)),
+        (Ok(_), Ok(_)) => Err(format!("{},{} is off the map", x.trim(), y.trim())),
+        _ => Err(format!("`{},{}` are not coordinates", x.trim(), y.trim())),
+    }
+}
+
+fn parse_direction(word: &str) -> Option<(i32, i32)> {
+    match word.to_ascii_lowercase().as_str() {
+        "n" | "north" => Some((0, -1)),
+        "s" | "south" => Some((0, 1)),
+        "e" | "east" => Some((1, 0)),
+        "w" | "west" => Some((-1, 0)),
+        "ne" | "northeast" => Some((1, -1)),
+        "nw" | "northwest" => Some((-1, -1)),
+        "se" | "southeast" => Some((1, 1)),
+        "sw" | "southwest" => Some((-1, 1)),
+        _ => None,
+    }
+}
+
+/// Walks from the player in `direction` until it reaches a monster, or the last tile before
+/// a wall or the edge of the player's sight.
+fn first_in_line(game: &GameState, (dx, dy): (i32, i32)) -> (i32, i32) {
+    let (mut x, mut y) = game.objects[PLAYER].pos();
+    loop {
+        let (next_x, next_y) = (x + dx, y + dy);
+        if out_of_bounds(next_x, next_y)
+            || game.map[next_x as usize][next_y as usize].blocked
+            || !game.fov_map.is_in_fov(next_x, next_y) {
+            return (x, y);
+        }
+        x = next_x;
+        y = next_y;
+        let monster_here = game.objects
+            .iter()
+            .any(|object| object.ai.is_some() && object.alive && object.pos() == (x, y));
+        if monster_here {
+            return (x, y);
+        }
+    }
+}
+
+/// If `line` executes a file that has to be aimed but gives no target, returns the file's
+/// targeting so the frontend can let the player pick one.
+pub fn missing_target(game: &GameState, line: &str) -> Option<Targeting> {
+    let commands = split_commands(line);
+    let tokens = match commands.as_slice() {
+        [command] => tokenize(command).ok()?,
+        _ => return None,
+    };
+    match tokens.as_slice() {
+        [command, name] if command.eq_ignore_ascii_case("exec") => {
+            let id = get_inventory_item_by_name(&game.inventory, name)?;
+            let targeting = game.inventory[id].item.as_ref()?.targeting;
+            if targeting == Targeting::None { None } else { Some(targeting) }
+        }
+        _ => None,
+    }
+}
+
+fn alias(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    match args {
+        [] => {
+            if game.aliases.is_empty() {
+                message(&mut game.messages, "No aliases defined.", WHITE);
+            }
+            let lines: Vec<String> = game.aliases
+                .iter()
+                .map(|(name, expansion)| format!("{} = {}", name, expansion))
+                .collect();
+            for line in lines {
+                message(&mut game.messages, line, WHITE);
+            }
+        }
+        [name] => {
+            let name = name.to_ascii_lowercase();
+            let expansion = game.aliases.get(&name)
+                .ok_or_else(|| format!("alias: `{}` is not defined", name))?
+                .clone();
+            message(&mut game.messages, format!("{} = {}", name, expansion), WHITE);
+        }
+        [name, command @ ..] => {
+            let name = name.to_ascii_lowercase();
+            if game.commands.get(&name).is_some() {
+                return Err(format!("alias: `{}` is a built-in command", name));
+            }
+            // A single argument is taken as written, so `alias x "a; b"` can chain commands
+            let expansion = match command {
+                [text] => text.clone(),
+                _ => command.iter().map(|word| quote_word(word)).collect::<Vec<_>>().join(" "),
+            };
+            message(&mut game.messages, format!("{} = {}", name, expansion), WHITE);
+            game.aliases.insert(name, expansion);
+        }
+    }
+    Ok(PlayerAction::NoTurn)
+}
+
+fn unalias(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    match args {
+        [name] => {
+            game.aliases.remove(&name.to_ascii_lowercase())
+                .ok_or_else(|| format!("unalias: `{}` is not defined", name))?;
+            Ok(PlayerAction::NoTurn)
+        }
+        _ => Err(usage(&game.commands.get("unalias").unwrap())),
+    }
+}
+
+/// Queues every command in a macro file: one command line per line, with blank lines
+/// and lines starting with `#` skipped.
+fn source(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let name = match args {
+        [name] => name,
+        _ => return Err(usage(&game.commands.get("source").unwrap())),
+    };
+    let path = Path::new(name);
+    let text = fs::read_to_string(path)
+        .or_else(|_| fs::read_to_string(Path::new(MACRO_DIR).join(path)))
+        .map_err(|_| format!("source: `{}`: Macro Not Found", name))?;
+
+    let commands: Vec<String> = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .flat_map(split_commands)
+        .collect();
+    queue_commands(game, commands);
+    Ok(PlayerAction::NoTurn)
+}
+
+/// Looks up the single App argument that `install` and `uninstall` expect.
+fn app_arg(game: &GameState, command: &str, args: &[String]) -> Result<usize, String> {
+    let id = file_arg(game, command, args)?;
+    if game.inventory[id].item_type != Some(ItemType::App) {
+        return Err(format!("{}: `{}` is not an App", command, game.inventory[id].name));
+    }
+    Ok(id)
+}
+
+fn install(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let id = app_arg(game, "install", args)?;
+    let used_slots = installed_apps(&game.inventory).len();
+    let name = game.inventory[id].name.clone();
+    match game.inventory[id].equipment.as_mut() {
+        Some(app) if app.installed => Err(format!("install: `{}` is already installed", name)),
+        Some(_) if used_slots >= APP_SLOTS => {
+            Err(format!("install: all {} slots are in use. Uninstall an App first", APP_SLOTS))
+        }
+        Some(app) => {
+            app.installed = true;
+            message(&mut game.messages, format!("Installed `{}`.", name), LIGHT_GREEN);
+            Ok(PlayerAction::TookTurn(ACTION_COST))
+        }
+        None => Err(format!("install: `{}` is not an App", name)),
+    }
+}
+
+fn uninstall(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let id = app_arg(game, "uninstall", args)?;
+    let name = game.inventory[id].name.clone();
+    match game.inventory[id].equipment.as_mut() {
+        Some(app) if app.installed => app.installed = false,
+        _ => return Err(format!("uninstall: `{}` is not installed", name)),
+    }
+
+    // Losing a max HP bonus can leave the player above their new maximum
+    let max_hp = game.objects[PLAYER].max_hp(&installed_apps(&game.inventory));
+    if let Some(fighter) = game.objects[PLAYER].fighter.as_mut() {
+        fighter.hp = cmp::min(fighter.hp, max_hp);
+    }
+    message(&mut game.messages, format!("Uninstalled `{}`.", name), LIGHT_GREEN);
+    Ok(PlayerAction::TookTurn(ACTION_COST))
+}
+
+/// Drops a whole stack, or just `count` files from it.
+fn drop(game: &mut GameState, args: &[String]) -> Result<PlayerAction, String> {
+    let (name, count) = match args {
+        [name] => (name, None),
+        [name, count] => match count.parse::<u32>() {
+            Ok(count) if count > 0 => (name, Some(count)),
+            _ => return Err(format!("drop: `{}` is not a number of files", count)),
+        },
+        _ => return Err(usage(&game.commands.get("drop").unwrap())),
+    };
+    let id = get_inventory_item_by_name(&game.inventory, name)
+        .ok_or_else(|| format!("drop: `{}`: File Not Found", name))?;
+    if game.inventory[id].equipment.is_some_and(|app| app.installed) {
+        return Err(format!("drop: `{}` is installed. Uninstall it first", game.inventory[id].name));
+    }
+
+    let count = cmp::min(count.unwrap_or(game.inventory[id].count), game.inventory[id].count);
+    drop_item(id, count, &mut game.inventory, &mut game.objects, &mut game.messages);
+    Ok(PlayerAction::TookTurn(ACTION_COST))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::ai::AiState;
+    use crate::game::tests::test_game;
+
+    fn words(line: &str) -> Vec<String> {
+        tokenize(line).unwrap()
+    }
+
+    #[test]
+    fn splits_on_any_run_of_whitespace() {
+        assert_eq!(words("  exec\tTracert   n "), vec!["exec", "Tracert", "n"]);
+    }
+
+    #[test]
+    fn empty_input_has_no_words() {
+        assert!(words("").is_empty());
+        assert!(words("   ").is_empty());
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(words("cat \"core dump\""), vec!["cat", "core dump"]);
+        assert_eq!(words("cat 'core dump'"), vec!["cat", "core dump"]);
+        // A quote can start partway through a word and leave the rest of it attached
+        assert_eq!(words("cat core' 'dump.log"), vec!["cat", "core dump.log"]);
+    }
+
+    #[test]
+    fn each_quote_keeps_the_other_literally() {
+        assert_eq!(words("alias hi 'cat \"read me\"'"), vec!["alias", "hi", "cat \"read me\""]);
+        assert_eq!(words("cat \"it's\""), vec!["cat", "it's"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_an_empty_word() {
+        assert_eq!(words("drop \"\""), vec!["drop", ""]);
+    }
+
+    #[test]
+    fn backslash_escapes_the_next_character() {
+        assert_eq!(words("cat core\\ dump"), vec!["cat", "core dump"]);
+        assert_eq!(words("cat \"say \\\"hi\\\"\""), vec!["cat", "say \"hi\""]);
+        assert_eq!(words("cat back\\\\slash"), vec!["cat", "back\\slash"]);
+    }
+
+    #[test]
+    fn rejects_an_unterminated_quote() {
+        assert_eq!(tokenize("cat \"core dump"), Err("Unterminated \" quote".to_string()));
+        assert_eq!(tokenize("cat 'core dump"), Err("Unterminated ' quote".to_string()));
+    }
+
+    #[test]
+    fn rejects_a_trailing_backslash() {
+        assert!(tokenize("cat core\\").is_err());
+    }
+
+    #[test]
+    fn quote_word_leaves_plain_words_alone() {
+        assert_eq!(quote_word("Tracert"), "Tracert");
+        assert_eq!(quote_word("core dump"), "\"core dump\"");
+    }
+
+    #[test]
+    fn quote_word_round_trips_through_tokenize() {
+        for word in &["Tracert", "core dump", "it's", "say \"hi\"", "back\\slash", "tab\there", ""] {
+            assert_eq!(words(&quote_word(word)), vec![word.to_string()], "{:?}", word);
+        }
+    }
+
+    #[test]
+    fn splits_commands_on_semicolons() {
+        assert_eq!(split_commands("ls; cat junk;info junk"), vec!["ls", " cat junk", "info junk"]);
+    }
+
+    #[test]
+    fn keeps_quoted_and_escaped_semicolons() {
+        assert_eq!(split_commands("alias both \"ls; ls\"; ls"), vec!["alias both \"ls; ls\"", " ls"]);
+        assert_eq!(split_commands("cat 'a;b'"), vec!["cat 'a;b'"]);
+        assert_eq!(split_commands("cat a\\;b"), vec!["cat a\\;b"]);
+    }
+
+    #[test]
+    fn drops_empty_commands() {
+        assert_eq!(split_commands("ls;; ;cat junk;"), vec!["ls", "cat junk"]);
+        assert!(split_commands(" ; ").is_empty());
+    }
+
+    #[test]
+    fn each_chained_command_takes_its_own_turn() {
+        let mut game = test_game();
+        let result = run_line(&mut game, "drop junk 1; ls; drop junk 1");
+        assert_eq!(result, PlayerAction::TookTurn(ACTION_COST));
+        assert_eq!(game.stats.turns, 2);
+        assert_eq!(game.inventory[0].count, 3);
+    }
+
+    #[test]
+    fn a_failed_command_does_not_stop_the_rest() {
+        let mut game = test_game();
+        run_line(&mut game, "cat missing; drop junk 1");
+        assert_eq!(game.inventory[0].count, 4);
+    }
+
+    #[test]
+    fn stops_an_alias_that_runs_itself() {
+        let mut game = test_game();
+        game.aliases.insert("spin".to_string(), "spin".to_string());
+        assert_eq!(run_line(&mut game, "spin"), PlayerAction::NoTurn);
+        assert!(game.pending.is_empty());
+        assert_eq!(game.messages.last().unwrap().0, "Too many chained commands. Stopping.");
+    }
+
+    #[test]
+    fn stops_when_a_monster_comes_into_view() {
+        let mut game = test_game();
+        let data = game.data.clone();
+        let mut virus = Object::new_monster(17, 15, "virus", &data.spawns.monsters["virus"], 2);
+        virus.ai.as_mut().unwrap().state = AiState::Hunting { target: (12, 15) };
+        game.objects.push(virus);
+
+        run_line(&mut game, "drop junk 1; drop junk 1; drop junk 1; drop junk 1");
+        // It closes in a tile a turn, and is in torchlight after the second drop
+        assert_eq!(game.inventory[0].count, 3);
+        assert!(game.pending.is_empty());
+        assert_eq!(game.messages.last().unwrap().0, "virus came into view. Remaining commands aborted.");
+    }
+
+    #[test]
+    fn look_tells_whether_a_monster_is_asleep() {
+        let mut game = test_game();
+        let data = game.data.clone();
+        game.objects.push(Object::new_monster(15, 15, "spambot", &data.spawns.monsters["spambot"], 2));
+
+        assert_eq!(run_command(&mut game, "look e"), PlayerAction::NoTurn);
+        assert_eq!(game.messages.last().unwrap().0, "(15, 15): spambot (asleep)");
+        run_command(&mut game, "look 12,15");
+        assert_eq!(game.messages.last().unwrap().0, "(12, 15): player");
+    }
+}